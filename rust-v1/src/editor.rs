@@ -0,0 +1,311 @@
+use std::collections::HashMap;
+
+use crate::interpreter::{ShockError, ErrorKind};
+use crate::model::PrimitiveData;
+use crate::placemodel::storage::{PlaceStore, StoreError};
+use crate::primitive::types::{Place, PlaceId, AttributeData};
+
+/// Where an editing session is in the place graph: the attributes that lead from the root to the
+/// focused place, with the place each one links to.
+pub type Focus = Vec<(String, PlaceId)>;
+
+/// The state of a structural editing session, e.g. in the REPL. Commands like `focus .main.body`
+/// and `insert` work relative to the focused place, which starts at the root module `$`.
+#[derive(Debug, Clone)]
+pub struct Editor {
+    root: PlaceId,
+    focus: Focus,
+    /// The focuses that `unfocus` goes back to, most recent last.
+    stack: Vec<Focus>,
+}
+
+impl Editor {
+    /// Starts a session at a new root module, which becomes the root of `store`.
+    pub fn new(store: &mut dyn PlaceStore) -> Editor {
+        let root = new_place("Module");
+        let id = root.get_id();
+        store.put_place(root).expect("A new place cannot be frozen.");
+        store.set_root(&id).expect("A new place has no owner.");
+        Editor { root: id, focus: vec![], stack: vec![] }
+    }
+
+    pub fn root(&self) -> PlaceId {
+        self.root
+    }
+
+    /// The focused place.
+    pub fn current(&self) -> PlaceId {
+        self.focus.last().map_or(self.root, |(_, id)| *id)
+    }
+
+    pub fn focus(&self) -> &Focus {
+        &self.focus
+    }
+
+    /// How the focused place is reached from the root, e.g. `$.main.body`.
+    pub fn describe(&self, focus: &Focus) -> String {
+        focus.iter().fold("$".to_owned(), |path, (name, _)| path + "." + name)
+    }
+
+    /// Finds where a path leads from the focus. A name follows the link of that name, and an
+    /// empty component goes back up towards the root. A path of only dots is `.` for the focus
+    /// and one more dot for each step up, like `..` for the parent.
+    pub fn resolve(&self, store: &dyn PlaceStore, path: &[String]) -> Result<Focus, ShockError> {
+        if path.iter().all(String::is_empty) && !path.is_empty() {
+            self.walk(store, &path[1..])
+        } else {
+            self.walk(store, path)
+        }
+    }
+
+    /// Follows the components of a path from the focus, one step each, e.g. the path to the
+    /// parent of the place `..locals` is created at.
+    pub fn walk(&self, store: &dyn PlaceStore, path: &[String]) -> Result<Focus, ShockError> {
+        let mut focus = self.focus.clone();
+        for part in path.iter() {
+            if part.is_empty() {
+                if focus.pop().is_none() {
+                    return Err(ShockError::new(ErrorKind::OutOfBounds, "The path goes above the root `$`."));
+                }
+                continue;
+            }
+            let from = focus.last().map_or(self.root, |(_, id)| *id);
+            let next = match get_place(store, from)?.get_attr(part) {
+                None => return Err(ShockError::new(
+                    ErrorKind::OutOfBounds,
+                    format!("`{}` has no attribute `{}`.", self.describe(&focus), part))),
+                Some(AttributeData::Place(id)) | Some(AttributeData::Reference(id)) => *id,
+                Some(AttributeData::Data(data)) => return Err(ShockError::new(
+                    ErrorKind::TypeMismatch,
+                    format!("`{}.{}` holds {}, not a place.", self.describe(&focus), part, data))),
+            };
+            get_place(store, next)?;
+            focus.push((part.clone(), next));
+        }
+        Ok(focus)
+    }
+
+    /// Focuses on another place, remembering the current focus for `unfocus`.
+    pub fn focus_on(&mut self, focus: Focus) {
+        let previous = std::mem::replace(&mut self.focus, focus);
+        self.stack.push(previous);
+    }
+
+    /// Goes back to the focus before the last `focus`. Focuses on places that were removed since
+    /// are skipped.
+    pub fn unfocus(&mut self, store: &dyn PlaceStore) -> Result<(), ShockError> {
+        while let Some(focus) = self.stack.pop() {
+            if focus.iter().all(|(_, id)| store.get_place(id).is_some()) {
+                self.focus = focus;
+                return Ok(());
+            }
+        }
+        Err(ShockError::new(ErrorKind::OutOfBounds, "There is no earlier focus to go back to."))
+    }
+
+    /// Whether the place is the focus or on the way to it, so that removing it would leave the
+    /// session nowhere.
+    pub fn is_focused(&self, id: PlaceId) -> bool {
+        id == self.root || self.focus.iter().any(|(_, focused)| *focused == id)
+    }
+}
+
+/// Makes a place of a type. Procedures start with places for their arguments and body.
+pub fn create(
+    store: &mut dyn PlaceStore,
+    kind: &str,
+    attributes: HashMap<String, AttributeData>) -> Result<PlaceId, StoreError> {
+    let mut place = new_place(kind);
+    for (name, data) in attributes.into_iter() {
+        place.put_attr(name, data);
+    }
+    if kind == "Procedure" {
+        for (name, part) in [("args", "Struct"), ("body", "List")].iter() {
+            let child = new_place(part);
+            place.put_attr(name.to_string(), AttributeData::Place(child.get_id()));
+            store.put_place(child)?;
+        }
+    }
+    let id = place.get_id();
+    store.put_place(place)?;
+    Ok(id)
+}
+
+fn new_place(kind: &str) -> Place {
+    let mut place = Place::generate_new();
+    place.put_attr("type".to_owned(), AttributeData::Data(PrimitiveData::Name(kind.to_owned())));
+    place
+}
+
+pub fn get_place(store: &dyn PlaceStore, id: PlaceId) -> Result<&Place, ShockError> {
+    store.get_place(&id).ok_or_else(|| ShockError::new(
+        ErrorKind::MissingPlace,
+        format!("The place {} is not in the place store.", id)))
+}
+
+/// The type a place was created with, or `Place` for places made with `place`.
+pub fn type_of(place: &Place) -> String {
+    match place.get_attr(&"type".to_owned()) {
+        Some(AttributeData::Data(PrimitiveData::Name(kind))) => kind.clone(),
+        _ => "Place".to_owned(),
+    }
+}
+
+/// The elements of a list place are its attributes `0`, `1` and so on.
+pub fn elements(place: &Place) -> Vec<AttributeData> {
+    (0..).map(|i: usize| place.get_attr(&i.to_string()).cloned())
+        .take_while(Option::is_some)
+        .map(Option::unwrap)
+        .collect()
+}
+
+/// Replaces the elements of a list place.
+pub fn set_elements(place: &mut Place, new_elements: Vec<AttributeData>) {
+    for i in 0..elements(place).len() {
+        place.remove_attr(&i.to_string());
+    }
+    for (i, element) in new_elements.into_iter().enumerate() {
+        place.put_attr(i.to_string(), element);
+    }
+}
+
+/// Lists a place and its attributes, or the elements of a list, one per line. References are
+/// marked with `->`.
+pub fn list(store: &dyn PlaceStore, title: &str, place: &Place) -> String {
+    let summary = |id: &PlaceId| match store.get_place(id) {
+        None => format!("missing place {}", id),
+        Some(place) if type_of(place) == "List" => format!("List [... {}]", elements(place).len()),
+        Some(place) => type_of(place),
+    };
+    let describe = |data: &AttributeData| match data {
+        AttributeData::Data(data) => data.to_string(),
+        AttributeData::Place(id) => summary(id),
+        AttributeData::Reference(id) => format!("-> {}", summary(id)),
+    };
+    let kind = type_of(place);
+    if kind == "List" {
+        let mut listing = format!("{}: List [", title);
+        for element in elements(place).iter() {
+            listing += &format!("\n    {}", describe(element));
+        }
+        return listing + "\n]";
+    }
+    let mut listing = format!("{}: {}", title, kind);
+    let mut attributes: Vec<(&String, &AttributeData)> = place.attributes()
+        .filter(|(name, _)| name.as_str() != "type")
+        .collect();
+    attributes.sort_by(|a, b| a.0.cmp(b.0));
+    for (name, data) in attributes.into_iter() {
+        listing += &format!("\n    .{}: {}", name, describe(data));
+    }
+    listing
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Engine;
+    use crate::engine::tests::Buffer;
+    use crate::interpreter::{Value, ErrorKind};
+    use crate::engine::EngineError;
+    use crate::model::PrimitiveData;
+    use crate::placemodel::storage::PlaceStore;
+
+    fn fail(engine: &mut Engine, source: &str) -> ErrorKind {
+        match engine.eval_str(source) {
+            Err(EngineError::Eval(err)) => err.kind,
+            other => panic!("Expected an error, but got {:?}.", other),
+        }
+    }
+
+    #[test]
+    fn editing_session() {
+        // Given: a session at the root module
+        let mut engine = Engine::new();
+        let out = Buffer::default();
+        engine.set_stdout(out.clone());
+
+        // When: we create a procedure and insert into its body
+        engine.eval_str("create Procedure at: main").unwrap();
+        engine.eval_str("focus .main.body").unwrap();
+        engine.eval_str("insert \"print\"").unwrap();
+        engine.eval_str("insert item: \"first\" at: 0u").unwrap();
+        engine.eval_str("list").unwrap();
+
+        // Then: the body lists its elements in order
+        assert_eq!("$.main.body: List [\n    \"first\"\n    \"print\"\n]\n", out.contents());
+
+        // When: we go up and list the procedure
+        // Then: its attributes are listed by name
+        engine.eval_str("focus ..").unwrap();
+        engine.eval_str("list").unwrap();
+        assert!(out.contents().ends_with("$.main: Procedure\n    .args: Struct\n    .body: List [... 2]\n"));
+
+        // When: we go back and remove an element
+        // Then: the ones after it move up
+        engine.eval_str("unfocus").unwrap();
+        assert_eq!(
+            Value::Primitive(PrimitiveData::String("first".to_owned())),
+            engine.eval_str("remove at: 0").unwrap());
+        assert_eq!(
+            Value::Primitive(PrimitiveData::String("print".to_owned())),
+            engine.eval_str("remove at: 0u8").unwrap());
+        assert_eq!(ErrorKind::OutOfBounds, fail(&mut engine, "remove at: 0"));
+    }
+
+    #[test]
+    fn invalid_edits() {
+        let mut engine = Engine::new();
+        engine.eval_str("create Procedure at: main").unwrap();
+
+        // Then: paths must lead to places, and not above the root
+        assert_eq!(ErrorKind::OutOfBounds, fail(&mut engine, "focus .nope"));
+        assert_eq!(ErrorKind::OutOfBounds, fail(&mut engine, "focus .."));
+        assert_eq!(ErrorKind::InvalidEdit, fail(&mut engine, "create List at: main"));
+        assert_eq!(ErrorKind::InvalidEdit, fail(&mut engine, "insert 1"));
+        assert_eq!(ErrorKind::InvalidEdit, fail(&mut engine, "remove at: 0"));
+
+        // Given: a focus inside a procedure
+        // Then: the procedure and the root cannot be removed
+        engine.eval_str("focus .main.args").unwrap();
+        assert_eq!(ErrorKind::InvalidEdit, fail(&mut engine, "remove at: .."));
+        assert_eq!(ErrorKind::InvalidEdit, fail(&mut engine, "remove at: ..."));
+
+        // When: the focus moves out, and the procedure is removed
+        // Then: it is gone with its parts, and `unfocus` skips the focuses inside it
+        engine.eval_str("focus ...").unwrap();
+        engine.eval_str("rm at: .main").unwrap();
+        {
+            let vm = engine.vm().lock().unwrap();
+            assert_eq!(vec![vm.editor.root()], vm.places.place_ids());
+        }
+        assert_eq!(ErrorKind::OutOfBounds, fail(&mut engine, "focus .main"));
+        engine.eval_str("unfocus").unwrap();
+        assert_eq!(Vec::<(String, _)>::new(), *engine.vm().lock().unwrap().editor.focus());
+        assert_eq!(ErrorKind::OutOfBounds, fail(&mut engine, "unfocus"));
+    }
+
+    #[test]
+    fn frozen_places() {
+        // Given: a procedure, frozen along with everything in it
+        let mut engine = Engine::new();
+        engine.eval_str("create Procedure at: main").unwrap();
+        engine.eval_str("freeze at: .main tree: true").unwrap();
+
+        // Then: none of it can be edited or removed
+        assert_eq!(ErrorKind::Frozen, fail(&mut engine, "remove at: .main"));
+        engine.eval_str("focus .main.body").unwrap();
+        assert_eq!(ErrorKind::Frozen, fail(&mut engine, "insert 1"));
+
+        // When: only the procedure is thawed
+        // Then: places can be created in it, but its body is still frozen
+        engine.eval_str("th at: ..").unwrap();
+        engine.eval_str("create List at: ..locals").unwrap();
+        assert_eq!(ErrorKind::Frozen, fail(&mut engine, "insert 1"));
+
+        // When: the body is thawed too, twice
+        // Then: it can be edited again
+        engine.eval_str("thaw at: .").unwrap();
+        engine.eval_str("thaw at: .").unwrap();
+        engine.eval_str("insert 1").unwrap();
+    }
+}
@@ -0,0 +1,258 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use crate::interpreter::{VM, VMScope, Value, ShockError, ErrorKind, FromValue, IntoValue, IntoNative,
+                         Observer, eval, call_value};
+use crate::parser::{FileId, ParseError, parse_file};
+
+/// An error from `Engine::eval_str` or `Engine::eval_file`.
+#[derive(Debug)]
+pub enum EngineError {
+    /// The source file could not be read.
+    Io(io::Error),
+    Parse(ParseError),
+    Eval(ShockError),
+}
+
+impl fmt::Display for EngineError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            EngineError::Io(err) => write!(f, "error[io]: {}", err),
+            EngineError::Parse(err) => write!(f, "{}", err),
+            EngineError::Eval(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl std::error::Error for EngineError {}
+
+impl From<ShockError> for EngineError {
+    fn from(err: ShockError) -> Self {
+        EngineError::Eval(err)
+    }
+}
+
+/// How many of the latest sources an engine keeps to render errors with. Errors from older
+/// sources, e.g. in a procedure defined early in a long REPL session, are rendered without a
+/// snippet.
+const MAX_SOURCES: usize = 64;
+
+/// A Shock interpreter for embedding in Rust programs, e.g. to use Shock as a configuration or
+/// scripting language.
+///
+/// ```ignore
+/// let mut engine = Engine::new();
+/// engine.register("double", |x: i64| x * 2);
+/// engine.eval_str("let port = (double 4040)")?;
+/// let port: i64 = engine.get_global("port")?;
+/// ```
+pub struct Engine {
+    vm: Arc<Mutex<VM>>,
+    global: Arc<Mutex<VMScope>>,
+    /// The latest sources that were evaluated, to render errors with.
+    sources: VecDeque<String>,
+    /// The file id of the oldest source in `sources`.
+    first_source: usize,
+}
+
+impl Engine {
+    /// Creates an engine with the standard library, writing to the process's stdout and stderr.
+    pub fn new() -> Engine {
+        let mut vm = VM::new();
+        vm.define_standard_functions();
+        let global = vm.get_current_scope();
+        Engine { vm: Arc::new(Mutex::new(vm)), global, sources: VecDeque::new(), first_source: 0 }
+    }
+
+    /// The underlying VM, for anything the engine does not cover.
+    pub fn vm(&self) -> &Arc<Mutex<VM>> {
+        &self.vm
+    }
+
+    pub fn set_stdout<W: Write + Send + 'static>(&mut self, sink: W) {
+        self.vm.lock().unwrap().stdout = Arc::new(Mutex::new(sink));
+    }
+
+    pub fn set_stderr<W: Write + Send + 'static>(&mut self, sink: W) {
+        self.vm.lock().unwrap().stderr = Arc::new(Mutex::new(sink));
+    }
+
+    /// Installs an observer for the evaluations that follow, e.g. a `trace::Tracer`, or removes
+    /// the one that is installed.
+    pub fn set_observer(&mut self, observer: Option<Observer>) {
+        self.vm.lock().unwrap().observer = observer;
+    }
+
+    /// Evaluates every expression in `source` in the global scope, returning the value of the
+    /// last one.
+    pub fn eval_str(&mut self, source: &str) -> Result<Value, EngineError> {
+        let file = FileId(self.first_source + self.sources.len());
+        self.sources.push_back(source.to_owned());
+        if self.sources.len() > MAX_SOURCES {
+            self.sources.pop_front();
+            self.first_source += 1;
+        }
+        let expressions = parse_file(source, file).map_err(EngineError::Parse)?;
+        let mut value = Value::Unit;
+        for expression in expressions.iter() {
+            value = eval(&self.vm, expression)?;
+        }
+        Ok(value)
+    }
+
+    pub fn eval_file<P: AsRef<Path>>(&mut self, path: P) -> Result<Value, EngineError> {
+        let source = fs::read_to_string(path).map_err(EngineError::Io)?;
+        self.eval_str(&source)
+    }
+
+    /// Defines a global variable, replacing any variable with the same name.
+    pub fn set_global<T: IntoValue>(&mut self, name: &str, value: T) {
+        self.global.lock().unwrap().set_value(name, &value.into_value());
+    }
+
+    /// Gets a global variable as a Rust value, e.g. `let port: i64 = engine.get_global("port")?`.
+    pub fn get_global<T: FromValue>(&self, name: &str) -> Result<T, ShockError> {
+        match self.global.lock().unwrap().lookup_direct_value(name) {
+            None => Err(ShockError::new(ErrorKind::UnboundName, format!("`{}` is not defined.", name))),
+            Some(value) => T::from_value(value),
+        }
+    }
+
+    /// Defines a Rust function or closure as a global native. See `VM::register`.
+    pub fn register<Args, F: IntoNative<Args>>(&mut self, name: &str, f: F) {
+        let native = Value::NativeProcedure(f.into_native());
+        self.global.lock().unwrap().set_value(name, &native);
+    }
+
+    /// Calls the global procedure `name` with positional arguments.
+    pub fn call(&mut self, name: &str, args: Vec<Value>) -> Result<Value, ShockError> {
+        let callee = self.get_global::<Value>(name)?;
+        let args = args.into_iter().map(|arg| (String::new(), arg)).collect();
+        call_value(&self.vm, callee, args)
+    }
+
+    /// Renders an error with a snippet of the source it happened in, if the engine evaluated it.
+    pub fn render(&self, err: &EngineError) -> String {
+        match err {
+            EngineError::Eval(err) => match err.span().and_then(|span| self.source(span.file)) {
+                Some(source) => err.render(err.span().unwrap().file, source),
+                None => err.to_string(),
+            },
+            err => err.to_string(),
+        }
+    }
+
+    /// The source with a file id, if it is still kept.
+    fn source(&self, file: FileId) -> Option<&String> {
+        file.0.checked_sub(self.first_source).and_then(|i| self.sources.get(i))
+    }
+}
+
+impl Default for Engine {
+    fn default() -> Self {
+        Engine::new()
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use crate::engine::{Engine, EngineError, MAX_SOURCES};
+    use crate::interpreter::{Value, ErrorKind};
+    use crate::model::PrimitiveData;
+    use std::io::{self, Write};
+    use std::sync::{Arc, Mutex};
+
+    /// A sink that tests can read back.
+    #[derive(Clone, Default)]
+    pub(crate) struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Buffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Buffer {
+        pub(crate) fn contents(&self) -> String {
+            String::from_utf8(self.0.lock().unwrap().clone()).unwrap()
+        }
+    }
+
+    #[test]
+    fn globals_and_calls() {
+        // Given: an engine with a global and a registered native
+        let mut engine = Engine::new();
+        engine.set_global("base", 40i64);
+        engine.register("double", |x: i64| x * 2);
+
+        // When: a script uses them and defines a procedure
+        engine.eval_str("let port = (+ base 2)\nlet offset = [x: Int]{ + (double x) port }").unwrap();
+
+        // Then: the host can read its globals and call its procedures
+        assert_eq!(42, engine.get_global::<i64>("port").unwrap());
+        assert_eq!(
+            Value::Primitive(PrimitiveData::Int(44)),
+            engine.call("offset", vec![Value::Primitive(PrimitiveData::Int(1))]).unwrap());
+        assert_eq!(ErrorKind::TypeMismatch, engine.get_global::<String>("port").unwrap_err().kind);
+        assert_eq!(ErrorKind::UnboundName, engine.call("nope", vec![]).unwrap_err().kind);
+    }
+
+    #[test]
+    fn output_goes_to_the_sinks() {
+        // Given: an engine that writes to buffers
+        let mut engine = Engine::new();
+        let (out, err) = (Buffer::default(), Buffer::default());
+        engine.set_stdout(out.clone());
+        engine.set_stderr(err.clone());
+
+        // When: a script prints
+        engine.eval_str("print \"port\" 42\neprint \"oops\"").unwrap();
+
+        // Then: the output is in the buffers
+        assert_eq!("port 42\n", out.contents());
+        assert_eq!("oops\n", err.contents());
+    }
+
+    #[test]
+    fn errors_point_into_their_source() {
+        let mut engine = Engine::new();
+        engine.eval_str("let x = 1").unwrap();
+
+        // Given: a script that fails to parse or to evaluate
+        // Then: the error says which
+        assert!(matches!(engine.eval_str("let y = ("), Err(EngineError::Parse(_))));
+        let err = engine.eval_str("\n+ x \"a\"").unwrap_err();
+        assert!(matches!(&err, EngineError::Eval(err) if err.kind == ErrorKind::TypeMismatch));
+
+        // Then: it is rendered with the line it happened on
+        assert!(engine.render(&err).contains("line 2"));
+        assert!(matches!(engine.eval_file("/nonexistent.shock"), Err(EngineError::Io(_))));
+    }
+
+    #[test]
+    fn only_the_latest_sources_are_kept() {
+        // Given: an error from a source that was evaluated first
+        let mut engine = Engine::new();
+        let old = engine.eval_str("\n+ 1 \"a\"").unwrap_err();
+        assert!(engine.render(&old).contains("line 2"));
+
+        // When: more sources are evaluated than the engine keeps
+        for i in 0..MAX_SOURCES {
+            engine.eval_str(&format!("let x = {}", i)).unwrap();
+        }
+
+        // Then: only the latest are kept, and the old error is rendered without its snippet
+        assert_eq!(MAX_SOURCES, engine.sources.len());
+        assert_eq!(old.to_string(), engine.render(&old));
+        let new = engine.eval_str("\n\n+ x \"a\"").unwrap_err();
+        assert!(engine.render(&new).contains("line 3"));
+    }
+}
@@ -1,60 +1,755 @@
 use std::collections::HashMap;
 
 use crate::model::PrimitiveData;
-use crate::parser::{ExpressionValue, Command};
+use crate::parser::{ExpressionValue, Command, FileId, Span, locate};
+use crate::trace::{EvalObserver, summarize, summarize_command};
+use crate::primitive::types::PlaceId;
+use crate::placemodel::storage::{PlaceStore, HashMapPlaceStore, StoreError};
+use crate::placemodel::context::Context;
+use crate::editor::Editor;
 use std::sync::Arc;
 use std::sync::Mutex;
 use std::fmt::Debug;
 use std::fmt::Formatter;
 use std::fmt;
 use std::mem;
+use std::ops::{Deref, DerefMut};
+use std::cmp::Ordering;
+use std::convert::TryFrom;
+use std::error::Error;
+use std::io::{self, Write};
+
+/// The kinds of errors that evaluating Shock code can run into.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorKind {
+    /// A variable, procedure or path that is not defined.
+    UnboundName,
+    /// A command whose name refers to something other than a procedure.
+    NotApplicable,
+    /// Missing, extra or unknown arguments.
+    Arity,
+    /// A value of the wrong kind, e.g. adding a string to a number.
+    TypeMismatch,
+    /// Overflow, division by zero, or a number that does not fit its kind.
+    Arithmetic,
+    /// An index past the end of a list, or a field that a struct does not have.
+    OutOfBounds,
+    /// A malformed procedure declaration, e.g. a duplicate parameter.
+    InvalidDeclaration,
+    /// Reading a source file or writing output failed.
+    Io,
+    /// A place or relation that is not in the VM's place store, e.g. because it was deleted.
+    MissingPlace,
+    /// An edit of the place graph that cannot be made, e.g. removing the focused place.
+    InvalidEdit,
+    /// A change to a place that is frozen in the VM's context.
+    Frozen,
+    /// A value raised by `raise`, or signaled by `signal` with no handler.
+    Raised,
+}
+
+impl ErrorKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ErrorKind::UnboundName => "unbound_name",
+            ErrorKind::NotApplicable => "not_applicable",
+            ErrorKind::Arity => "arity",
+            ErrorKind::TypeMismatch => "type_mismatch",
+            ErrorKind::Arithmetic => "arithmetic",
+            ErrorKind::OutOfBounds => "out_of_bounds",
+            ErrorKind::InvalidDeclaration => "invalid_declaration",
+            ErrorKind::Io => "io",
+            ErrorKind::MissingPlace => "missing_place",
+            ErrorKind::InvalidEdit => "invalid_edit",
+            ErrorKind::Frozen => "frozen",
+            ErrorKind::Raised => "raised",
+        }
+    }
+}
+
+/// A command that was being evaluated when an error happened.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Frame {
+    pub name: String,
+    pub span: Span,
+}
+
+/// An error from evaluating Shock code, along with the Shock call stack it unwound. It is boxed,
+/// so that the results it is returned in stay small, and derefs to its `ShockErrorInner`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ShockError(Box<ShockErrorInner>);
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ShockErrorInner {
+    pub kind: ErrorKind,
+    pub message: String,
+    /// The commands the error passed through, innermost first.
+    pub stack: Vec<Frame>,
+    /// The value that was raised, for errors raised by Shock code.
+    pub value: Option<Value>,
+}
+
+impl ShockError {
+    pub fn new<S: Into<String>>(kind: ErrorKind, message: S) -> ShockError {
+        ShockError(Box::new(ShockErrorInner { kind, message: message.into(), stack: vec![], value: None }))
+    }
+
+    pub fn raised(value: Value) -> ShockError {
+        ShockError(Box::new(ShockErrorInner {
+            kind: ErrorKind::Raised,
+            message: format!("Raised {:?}.", value),
+            stack: vec![],
+            value: Some(value),
+        }))
+    }
+
+    /// What a handler gets for this error: the raised value, or a struct with the kind and
+    /// message of any other error.
+    pub fn condition(&self) -> Value {
+        match &self.value {
+            Some(value) => value.clone(),
+            None => Value::Struct(vec![
+                ("kind".to_owned(), Value::Primitive(PrimitiveData::Name(self.kind.name().to_owned()))),
+                ("message".to_owned(), Value::Primitive(PrimitiveData::String(self.message.clone()))),
+            ]),
+        }
+    }
+    
+    /// Where the error happened, if it happened inside a command.
+    pub fn span(&self) -> Option<Span> {
+        self.stack.first().map(|frame| frame.span)
+    }
+    
+    fn in_command(mut self, command: &Command) -> ShockError {
+        self.stack.push(Frame { name: command.name.clone(), span: command.span });
+        self
+    }
+    
+    /// Renders the error with a snippet of `source`, which is the text of `file`. Frames from
+    /// other files are shown with their byte spans.
+    pub fn render(&self, file: FileId, source: &str) -> String {
+        let mut rendered = format!("error[{}]: {}", self.kind.name(), self.message);
+        if let Some(span) = self.span().filter(|span| span.file == file) {
+            let (_, _, snippet) = locate(source, span.start..span.end);
+            rendered += &format!("\n{}", snippet);
+        }
+        for frame in self.stack.iter() {
+            if frame.span.file == file {
+                let (line, column, _) = locate(source, frame.span.start..frame.span.end);
+                rendered += &format!("\n    in `{}` at line {}, column {}", frame.name, line, column);
+            } else {
+                rendered += &format!("\n    in `{}` at {} of file {}", frame.name, frame.span, frame.span.file.0);
+            }
+        }
+        rendered
+    }
+}
+
+impl Deref for ShockError {
+    type Target = ShockErrorInner;
+
+    fn deref(&self) -> &ShockErrorInner {
+        &self.0
+    }
+}
+
+impl DerefMut for ShockError {
+    fn deref_mut(&mut self) -> &mut ShockErrorInner {
+        &mut self.0
+    }
+}
+
+impl fmt::Display for ShockError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "error[{}]: {}", self.kind.name(), self.message)?;
+        for frame in self.stack.iter() {
+            write!(f, "\n    in `{}` at {}", frame.name, frame.span)?;
+        }
+        Ok(())
+    }
+}
+
+impl Error for ShockError {}
+
+impl From<StoreError> for ShockError {
+    fn from(err: StoreError) -> Self {
+        match err {
+            StoreError::Frozen(_) => ShockError::new(ErrorKind::Frozen, err.to_string()),
+            StoreError::Missing(_) | StoreError::MissingRelation(_) =>
+                ShockError::new(ErrorKind::MissingPlace, err.to_string()),
+            StoreError::AlreadyOwned(_) | StoreError::OwnershipCycle(_) | StoreError::Referenced(_)
+            | StoreError::UnnamedLink(_) | StoreError::TransactionOpen | StoreError::NoTransaction =>
+                ShockError::new(ErrorKind::InvalidEdit, err.to_string()),
+        }
+    }
+}
 
 pub trait Applicable {
-    fn apply(&mut self, args: Vec<(String, Value)>, vm: &Arc<Mutex<VM>>) -> Value;
+    fn apply(&mut self, args: Vec<(String, Value)>, vm: &Arc<Mutex<VM>>) -> Result<Value, ShockError>;
+}
+
+/// How deeply a `Code` can point into its tree. Code nested deeper than this, or past the 65535th
+/// child of a node, is copied into a tree of its own.
+const MAX_CODE_DEPTH: usize = 12;
+
+/// A handle to an expression inside a shared syntax tree. Cloning it does not copy the tree, so
+/// procedures and the evaluator can hold on to code cheaply.
+#[derive(Clone)]
+pub struct Code {
+    root: Arc<ExpressionValue>,
+    /// The child indices that lead from the root to the expression.
+    path: [u16; MAX_CODE_DEPTH],
+    depth: u16,
+}
+
+/// The commands of blocks and procedure bodies are not expressions on their own, so a `Code`
+/// can point at either.
+enum Node<'a> {
+    Expression(&'a ExpressionValue),
+    Command(&'a Command),
+}
+
+impl<'a> Node<'a> {
+    /// The children of a node are the arguments of a command, the elements of a list or struct,
+    /// the commands of a block, and the argument list and then the commands of a procedure.
+    fn child(self, i: usize) -> Node<'a> {
+        match self {
+            Node::Command(command) |
+            Node::Expression(ExpressionValue::Expression(command)) => Node::Expression(&command.args[i].1),
+            Node::Expression(ExpressionValue::List(elements, _)) => Node::Expression(&elements[i]),
+            Node::Expression(ExpressionValue::Struct(fields, _)) => Node::Expression(&fields[i].1),
+            Node::Expression(ExpressionValue::Block(commands, _)) => Node::Command(&commands[i]),
+            Node::Expression(ExpressionValue::Procedure(args, body, _)) => if i < args.len() {
+                Node::Expression(&args[i].1)
+            } else {
+                Node::Command(&body[i - args.len()])
+            },
+            Node::Expression(expr) => panic!("{:?} has no children.", expr),
+        }
+    }
+}
+
+impl Code {
+    pub fn new(expr: ExpressionValue) -> Code {
+        Code { root: Arc::new(expr), path: [0; MAX_CODE_DEPTH], depth: 0 }
+    }
+
+    fn node(&self) -> Node<'_> {
+        self.path[..self.depth as usize].iter()
+            .fold(Node::Expression(&self.root), |node, &i| node.child(i as usize))
+    }
+
+    pub fn child(&self, i: usize) -> Code {
+        if self.depth as usize == MAX_CODE_DEPTH || i > u16::MAX as usize {
+            return match self.node().child(i) {
+                Node::Expression(expr) => Code::new(expr.clone()),
+                Node::Command(command) => Code::new(ExpressionValue::Expression(command.clone())),
+            };
+        }
+        let mut child = self.clone();
+        child.path[self.depth as usize] = i as u16;
+        child.depth += 1;
+        child
+    }
+
+    /// The expression this points at. Panics if it points at a command of a block.
+    pub fn expr(&self) -> &ExpressionValue {
+        match self.node() {
+            Node::Expression(expr) => expr,
+            Node::Command(command) => panic!("`{}` is a command, not an expression.", command.name),
+        }
+    }
+
+    /// Where the code is in its source.
+    pub fn span(&self) -> Span {
+        match self.node() {
+            Node::Expression(expr) => expr.span(),
+            Node::Command(command) => command.span,
+        }
+    }
+
+    /// The code as a line of source, for traces.
+    pub fn summary(&self) -> String {
+        match self.node() {
+            Node::Expression(expr) => summarize(expr),
+            Node::Command(command) => summarize_command(command),
+        }
+    }
+
+    /// The command this points at, either on its own or as an expression.
+    fn command(&self) -> &Command {
+        match self.node() {
+            Node::Command(command) |
+            Node::Expression(ExpressionValue::Expression(command)) => command,
+            Node::Expression(expr) => panic!("{:?} is not a command.", expr),
+        }
+    }
+}
+
+impl PartialEq for Code {
+    fn eq(&self, other: &Code) -> bool {
+        match (self.node(), other.node()) {
+            (Node::Expression(a), Node::Expression(b)) => a == b,
+            (Node::Command(a), Node::Command(b)) => a == b,
+            _ => false,
+        }
+    }
+}
+
+impl Debug for Code {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self.node() {
+            Node::Expression(expr) => expr.fmt(f),
+            Node::Command(command) => command.fmt(f),
+        }
+    }
+}
+
+/// A declared parameter of a procedure, e.g. `x: Int` or `y: Int = 10`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Parameter {
+    pub name: String,
+    pub annotation: ExpressionValue,
+    pub default: Option<Code>,
 }
 
 #[derive(Debug, Clone)]
 pub struct Procedure {
-    pub argnames: Vec<String>,
-    pub body: Vec<Command>,
+    pub params: Vec<Parameter>,
+    /// The `-> Type` entry of the argument list. It is kept as metadata and not checked.
+    pub return_type: Option<ExpressionValue>,
+    /// The `[...]{...}` expression the procedure was made from.
+    pub code: Code,
     pub scope: Arc<Mutex<VMScope>>,
 }
 
-impl Applicable for Procedure {
-    fn apply(&mut self, args: Vec<(String, Value)>, vm: &Arc<Mutex<VM>>) -> Value {
-        // First, temporarily bind the variables in the current scope.
-        for binding in args.iter() {
-            vm.lock().unwrap().curr_scope.lock().unwrap().set_value(
-                &binding.0, &binding.1);
+impl Procedure {
+    /// Reads the parameters from an argument list like `[x: Int y: Int = 10 -> Int]`. An `=`
+    /// entry gives the parameter before it a default value, and a `->` entry is the return type.
+    pub fn new(code: Code, scope: Arc<Mutex<VMScope>>) -> Result<Procedure, ShockError> {
+        let invalid = |message: String| Err(ShockError::new(ErrorKind::InvalidDeclaration, message));
+        let args = match code.expr() {
+            ExpressionValue::Procedure(args, _, _) => args,
+            other => return invalid(format!("Expected a procedure at {}.", other.span())),
+        };
+        let mut params: Vec<Parameter> = vec![];
+        let mut return_type = None;
+        for (i, (label, value)) in args.iter().enumerate() {
+            match (label.as_str(), value) {
+                ("->", _) => return_type = Some(value.clone()),
+                ("=", _) => match params.last_mut() {
+                    Some(ref mut param) if param.default.is_none() =>
+                        param.default = Some(code.child(i)),
+                    _ => return invalid(format!("Misplaced default value at {}.", value.span())),
+                },
+                ("", ExpressionValue::Primitive(PrimitiveData::Name(name), _)) => params.push(Parameter {
+                    name: name.clone(),
+                    annotation: ExpressionValue::Unit,
+                    default: None,
+                }),
+                ("", _) => return invalid(format!("Expected a parameter name at {}.", value.span())),
+                (name, _) => params.push(Parameter {
+                    name: name.to_owned(),
+                    annotation: value.clone(),
+                    default: None,
+                }),
+            }
+        }
+        for (i, param) in params.iter().enumerate() {
+            if params[..i].iter().any(|other| other.name == param.name) {
+                return invalid(format!("Duplicate parameter `{}`.", param.name));
+            }
+        }
+        Ok(Procedure { params, return_type, code, scope })
+    }
+
+    pub fn body(&self) -> &[Command] {
+        match self.code.expr() {
+            ExpressionValue::Procedure(_, body, _) => body,
+            _ => unreachable!(),
+        }
+    }
+
+    /// The range of child indices of `code` that are the commands of the body.
+    fn body_range(&self) -> (usize, usize) {
+        match self.code.expr() {
+            ExpressionValue::Procedure(args, body, _) => (args.len(), args.len() + body.len()),
+            _ => unreachable!(),
+        }
+    }
+
+    /// Matches call arguments to parameters: labeled arguments go to the parameter with that
+    /// name, and unlabeled arguments fill the remaining parameters in order. Parameters left
+    /// without an argument get their default value, which is `None` here so that it can be
+    /// evaluated once the earlier parameters are bound.
+    pub fn bind_arguments(&self, args: Vec<(String, Value)>) -> Result<Vec<(String, Option<Value>)>, ShockError> {
+        let arity = |message: String| ShockError::new(ErrorKind::Arity, message);
+        let mut bound: Vec<Option<Value>> = vec![None; self.params.len()];
+        let mut positional = vec![];
+        for (label, value) in args.into_iter() {
+            if label.is_empty() {
+                positional.push(value);
+                continue;
+            }
+            match self.params.iter().position(|param| param.name == label) {
+                None => return Err(arity(format!("Unknown parameter `{}`.", label))),
+                Some(i) if bound[i].is_some() => return Err(arity(format!("Parameter `{}` was given twice.", label))),
+                Some(i) => bound[i] = Some(value),
+            }
+        }
+        
+        let unbound = bound.iter().filter(|slot| slot.is_none()).count();
+        if positional.len() > unbound {
+            return Err(arity(format!(
+                "Expected at most {} arguments, but got {}.",
+                self.params.len(), self.params.len() - unbound + positional.len())));
         }
-        // Evaluate each expression in the block, return Unit if empty.
-        let mut value : Value = Value::Unit;
-        for command in self.body.iter() {
-            value = eval(vm, &ExpressionValue::Expression(command.clone()));
+        let mut positional = positional.into_iter();
+        for slot in bound.iter_mut().filter(|slot| slot.is_none()) {
+            *slot = positional.next();
         }
-        value
+        
+        self.params.iter().zip(bound).map(|(param, value)| {
+            match (value, &param.default) {
+                (None, None) => Err(arity(format!("Missing argument for parameter `{}`.", param.name))),
+                (value, _) => Ok((param.name.clone(), value)),
+            }
+        }).collect()
+    }
+}
+
+impl Applicable for Procedure {
+    fn apply(&mut self, args: Vec<(String, Value)>, vm: &Arc<Mutex<VM>>) -> Result<Value, ShockError> {
+        call_value(vm, Value::Procedure(self.clone()), args)
     }
 }
 
+/// The signature of natives: they get their arguments as they were written, so names and paths
+/// are not looked up yet.
+pub type NativeFn = dyn Fn(Vec<(String, Value)>, &Arc<Mutex<VM>>) -> Result<Value, ShockError> + Send + Sync;
+
 #[derive(Clone)]
 pub struct NativeProcedure {
-    pub proc: fn(Vec<(String, Value)>, &Arc<Mutex<VM>>) -> Value,
+    pub proc: Arc<NativeFn>,
 }
 
 impl NativeProcedure {
-    fn new(proc: fn(Vec<(String, Value)>, &Arc<Mutex<VM>>) -> Value) -> Self {
-        NativeProcedure { proc: proc }
+    pub fn new<F>(proc: F) -> Self
+        where F: Fn(Vec<(String, Value)>, &Arc<Mutex<VM>>) -> Result<Value, ShockError> + Send + Sync + 'static {
+        NativeProcedure { proc: Arc::new(proc) }
     }
 }
 
 impl Debug for NativeProcedure {
     fn fmt(&self, f: &mut Formatter) -> fmt::Result {
-        write!(f, "NativeProcedure {{ proc: {:p} }}", self.proc as *const ())
+        write!(f, "NativeProcedure {{ proc: {:p} }}", Arc::as_ptr(&self.proc) as *const ())
     }
 }
 
 impl Applicable for NativeProcedure {
-    fn apply(&mut self, args: Vec<(String, Value)>, vm: &'_ Arc<Mutex<VM>>) -> Value { (self.proc)(args, vm) }
+    fn apply(&mut self, args: Vec<(String, Value)>, vm: &'_ Arc<Mutex<VM>>) -> Result<Value, ShockError> { (self.proc)(args, vm) }
+}
+
+/// Converts an argument of a native to a Rust value.
+pub trait FromValue: Sized {
+    fn from_value(value: Value) -> Result<Self, ShockError>;
+}
+
+/// Converts the result of a native to a Shock value.
+pub trait IntoValue {
+    fn into_value(self) -> Value;
+}
+
+/// What a registered native can return: a value, or a value or an error.
+pub trait IntoResult {
+    fn into_result(self) -> Result<Value, ShockError>;
+}
+
+impl<T: IntoValue> IntoResult for T {
+    fn into_result(self) -> Result<Value, ShockError> {
+        Ok(self.into_value())
+    }
+}
+
+impl<T: IntoValue> IntoResult for Result<T, ShockError> {
+    fn into_result(self) -> Result<Value, ShockError> {
+        self.map(IntoValue::into_value)
+    }
+}
+
+fn mismatch<T>(expected: &str, value: Value) -> Result<T, ShockError> {
+    Err(ShockError::new(ErrorKind::TypeMismatch, format!("Expected {}, but got {:?}.", expected, value)))
+}
+
+impl FromValue for Value {
+    fn from_value(value: Value) -> Result<Self, ShockError> {
+        Ok(value)
+    }
+}
+
+impl FromValue for i64 {
+    fn from_value(value: Value) -> Result<Self, ShockError> {
+        match value {
+            Value::Primitive(PrimitiveData::Int(i)) => Ok(i),
+            Value::Primitive(PrimitiveData::Byte(b)) => Ok(b as i64),
+            Value::Primitive(PrimitiveData::Unsigned(u)) if u <= i64::MAX as u64 => Ok(u as i64),
+            value => mismatch("an integer", value),
+        }
+    }
+}
+
+impl FromValue for u64 {
+    fn from_value(value: Value) -> Result<Self, ShockError> {
+        match value {
+            Value::Primitive(PrimitiveData::Unsigned(u)) => Ok(u),
+            Value::Primitive(PrimitiveData::Byte(b)) => Ok(b as u64),
+            Value::Primitive(PrimitiveData::Int(i)) if i >= 0 => Ok(i as u64),
+            value => mismatch("a non-negative integer", value),
+        }
+    }
+}
+
+impl FromValue for usize {
+    fn from_value(value: Value) -> Result<Self, ShockError> {
+        u64::from_value(value).map(|u| u as usize)
+    }
+}
+
+impl FromValue for u8 {
+    fn from_value(value: Value) -> Result<Self, ShockError> {
+        let byte = match &value {
+            Value::Primitive(PrimitiveData::Byte(b)) => Some(*b),
+            Value::Primitive(PrimitiveData::Int(i)) => u8::try_from(*i).ok(),
+            Value::Primitive(PrimitiveData::Unsigned(u)) => u8::try_from(*u).ok(),
+            _ => None,
+        };
+        byte.map_or_else(|| mismatch("a byte", value), Ok)
+    }
+}
+
+/// Integers widen to floats, like they do in arithmetic.
+impl FromValue for f64 {
+    fn from_value(value: Value) -> Result<Self, ShockError> {
+        match value {
+            Value::Primitive(PrimitiveData::Float(f)) => Ok(f),
+            Value::Primitive(PrimitiveData::Int(i)) => Ok(i as f64),
+            Value::Primitive(PrimitiveData::Unsigned(u)) => Ok(u as f64),
+            Value::Primitive(PrimitiveData::Byte(b)) => Ok(b as f64),
+            value => mismatch("a number", value),
+        }
+    }
+}
+
+impl FromValue for bool {
+    fn from_value(value: Value) -> Result<Self, ShockError> {
+        match value {
+            Value::Primitive(PrimitiveData::Bool(b)) => Ok(b),
+            value => mismatch("a boolean", value),
+        }
+    }
+}
+
+impl FromValue for String {
+    fn from_value(value: Value) -> Result<Self, ShockError> {
+        match value {
+            Value::Primitive(PrimitiveData::String(s)) => Ok(s),
+            value => mismatch("a string", value),
+        }
+    }
+}
+
+impl FromValue for PlaceId {
+    fn from_value(value: Value) -> Result<Self, ShockError> {
+        match value {
+            Value::PlaceRef(id) => Ok(id),
+            value => mismatch("a place", value),
+        }
+    }
+}
+
+impl<T: FromValue> FromValue for Vec<T> {
+    fn from_value(value: Value) -> Result<Self, ShockError> {
+        match value {
+            Value::List(elements) => elements.into_iter().map(T::from_value).collect(),
+            value => mismatch("a list", value),
+        }
+    }
+}
+
+impl IntoValue for Value {
+    fn into_value(self) -> Value {
+        self
+    }
+}
+
+impl IntoValue for () {
+    fn into_value(self) -> Value {
+        Value::Unit
+    }
+}
+
+impl IntoValue for i64 {
+    fn into_value(self) -> Value {
+        Value::Primitive(PrimitiveData::Int(self))
+    }
+}
+
+impl IntoValue for u64 {
+    fn into_value(self) -> Value {
+        Value::Primitive(PrimitiveData::Unsigned(self))
+    }
+}
+
+/// Sizes and positions are plain integers in Shock.
+impl IntoValue for usize {
+    fn into_value(self) -> Value {
+        Value::Primitive(PrimitiveData::Int(self as i64))
+    }
+}
+
+impl IntoValue for u8 {
+    fn into_value(self) -> Value {
+        Value::Primitive(PrimitiveData::Byte(self))
+    }
+}
+
+impl IntoValue for f64 {
+    fn into_value(self) -> Value {
+        Value::Primitive(PrimitiveData::Float(self))
+    }
+}
+
+impl IntoValue for bool {
+    fn into_value(self) -> Value {
+        Value::Primitive(PrimitiveData::Bool(self))
+    }
+}
+
+impl IntoValue for String {
+    fn into_value(self) -> Value {
+        Value::Primitive(PrimitiveData::String(self))
+    }
+}
+
+impl<'a> IntoValue for &'a str {
+    fn into_value(self) -> Value {
+        Value::Primitive(PrimitiveData::String(self.to_owned()))
+    }
+}
+
+impl IntoValue for PlaceId {
+    fn into_value(self) -> Value {
+        Value::PlaceRef(self)
+    }
+}
+
+impl<T: IntoValue> IntoValue for Vec<T> {
+    fn into_value(self) -> Value {
+        Value::List(self.into_iter().map(IntoValue::into_value).collect())
+    }
+}
+
+/// `None` is unit.
+impl<T: IntoValue> IntoValue for Option<T> {
+    fn into_value(self) -> Value {
+        self.map_or(Value::Unit, IntoValue::into_value)
+    }
+}
+
+/// Rust functions and closures that can be registered as natives with `VM::register`. `Args` is
+/// the tuple of their argument types.
+pub trait IntoNative<Args> {
+    fn into_native(self) -> NativeProcedure;
+}
+
+macro_rules! into_native_impl {
+    ($count: expr; $($arg: ident: $ty: ident),*) => {
+        impl<F, R, $($ty),*> IntoNative<($($ty,)*)> for F
+            where F: Fn($($ty),*) -> R + Send + Sync + 'static, R: IntoResult, $($ty: FromValue),* {
+            /// The native takes its arguments in order, ignoring labels, and looks up the ones
+            /// that are names or paths before converting them.
+            #[allow(unused_variables, unused_mut)]
+            fn into_native(self) -> NativeProcedure {
+                NativeProcedure::new(move |args, vm| {
+                    if args.len() != $count {
+                        return Err(ShockError::new(
+                            ErrorKind::Arity,
+                            format!("Expected {} arguments, but got {}.", $count, args.len())));
+                    }
+                    let mut args = args.into_iter();
+                    $(let $arg = $ty::from_value(nativelib::resolve(&args.next().unwrap().1, vm)?)?;)*
+                    (self)($($arg),*).into_result()
+                })
+            }
+        }
+    }
+}
+
+into_native_impl!(0;);
+into_native_impl!(1; a: A);
+into_native_impl!(2; a: A, b: B);
+into_native_impl!(3; a: A, b: B, c: C);
+into_native_impl!(4; a: A, b: B, c: C, d: D);
+into_native_impl!(5; a: A, b: B, c: C, d: D, e: E);
+
+/// The signature of special forms, which get the code of their arguments.
+pub type SpecialFormFn = fn(Vec<(String, Code)>, &Arc<Mutex<VM>>) -> Result<Step, ShockError>;
+
+/// A native that receives its arguments unevaluated, so that it can decide which of them to
+/// evaluate and when (e.g. `if` only evaluates one of its branches). It does so by returning a
+/// `Step` for the evaluator to carry out.
+#[derive(Clone)]
+pub struct SpecialForm {
+    pub form: SpecialFormFn,
+}
+
+impl SpecialForm {
+    fn new(form: SpecialFormFn) -> Self {
+        SpecialForm { form }
+    }
+
+    pub fn apply(&self, args: Vec<(String, Code)>, vm: &Arc<Mutex<VM>>) -> Result<Step, ShockError> {
+        (self.form)(args, vm)
+    }
+}
+
+/// Called by the evaluator with the value of an expression that a special form asked for.
+pub type Callback = Arc<dyn Fn(Value, &Arc<Mutex<VM>>) -> Result<Step, ShockError> + Send + Sync>;
+
+/// What a special form asks the evaluator to do next.
+pub enum Step {
+    /// The form is done, with this value.
+    Value(Value),
+    /// The value of the form is the value of this expression. It is evaluated in place of the
+    /// form, so a procedure call there is a tail call.
+    Eval(Code),
+    /// Evaluates the expression, then passes its value to the callback for the next step.
+    Then(Code, Callback),
+    /// Applies a procedure or continuation to the arguments, in place of the form.
+    Apply(Value, Vec<(String, Value)>),
+    /// Evaluates the expression as the delimiter of the continuations that `Shift` captures.
+    Reset(Code),
+    /// Captures the continuation up to the nearest `Reset` and calls the procedure with it. The
+    /// value of the procedure is then the value of the `Reset`.
+    Shift(Value),
+    /// Evaluates the expression, calling the handler if it fails or signals.
+    Try(Code, Value),
+    /// Calls the nearest handler with the condition and the continuation up to, and including,
+    /// the handler. The value of the handler is then the value of its `Try`.
+    Signal(Value),
+}
+
+impl Step {
+    pub fn then<F>(code: Code, callback: F) -> Step
+        where F: Fn(Value, &Arc<Mutex<VM>>) -> Result<Step, ShockError> + Send + Sync + 'static {
+        Step::Then(code, Arc::new(callback))
+    }
+}
+
+impl Debug for SpecialForm {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "SpecialForm {{ form: {:p} }}", self.form as *const ())
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -63,7 +758,90 @@ pub enum Value {
     Primitive(PrimitiveData),
     Procedure(Procedure),
     NativeProcedure(NativeProcedure),
+    SpecialForm(SpecialForm),
     Path(Vec<String>),
+    List(Vec<Value>),
+    Struct(Vec<(String, Value)>),
+    Continuation(Continuation),
+    /// A place in the VM's place store.
+    PlaceRef(PlaceId),
+}
+
+/// The rest of a computation, captured by `shift` or `signal`. It can be resumed any number of
+/// times, each time with a value for the expression that captured it.
+#[derive(Clone)]
+pub struct Continuation {
+    frames: Arc<Vec<Kont>>,
+    /// The scope the computation was in when it was captured.
+    scope: Arc<Mutex<VMScope>>,
+}
+
+impl Debug for Continuation {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "Continuation {{ frames: {} }}", self.frames.len())
+    }
+}
+
+/// Numbers are equal across kinds if they have the same value (`1 == 1u == 1.0`). Any other
+/// values are only equal to values of the same kind. Procedures and continuations are equal only
+/// to themselves.
+impl PartialEq for Value {
+    fn eq(&self, other: &Value) -> bool {
+        use crate::interpreter::nativelib::arith::Number;
+        
+        match (self, other) {
+            (Value::Unit, Value::Unit) => true,
+            (Value::Primitive(a), Value::Primitive(b)) =>
+                match (Number::from_primitive(a), Number::from_primitive(b)) {
+                    (Some(a), Some(b)) => a.compare(b) == Some(Ordering::Equal),
+                    _ => a == b,
+                },
+            (Value::Procedure(a), Value::Procedure(b)) =>
+                a.code == b.code && Arc::ptr_eq(&a.scope, &b.scope),
+            (Value::NativeProcedure(a), Value::NativeProcedure(b)) => Arc::ptr_eq(&a.proc, &b.proc),
+            (Value::SpecialForm(a), Value::SpecialForm(b)) =>
+                a.form as *const () == b.form as *const (),
+            (Value::Path(a), Value::Path(b)) => a == b,
+            (Value::List(a), Value::List(b)) => a == b,
+            (Value::Struct(a), Value::Struct(b)) => a == b,
+            (Value::Continuation(a), Value::Continuation(b)) => Arc::ptr_eq(&a.frames, &b.frames),
+            (Value::PlaceRef(a), Value::PlaceRef(b)) => a == b,
+            _ => false,
+        }
+    }
+}
+
+/// Numbers are ordered by value across kinds, `false < true`, strings and names are ordered
+/// lexicographically, and lists are ordered element by element. Values of different kinds,
+/// procedures and structs have no order.
+impl PartialOrd for Value {
+    fn partial_cmp(&self, other: &Value) -> Option<Ordering> {
+        use crate::interpreter::nativelib::arith::Number;
+        
+        match (self, other) {
+            (Value::Unit, Value::Unit) => Some(Ordering::Equal),
+            (Value::Primitive(a), Value::Primitive(b)) =>
+                match (Number::from_primitive(a), Number::from_primitive(b)) {
+                    (Some(a), Some(b)) => a.compare(b),
+                    _ => match (a, b) {
+                        (PrimitiveData::Bool(a), PrimitiveData::Bool(b)) => a.partial_cmp(b),
+                        (PrimitiveData::String(a), PrimitiveData::String(b)) => a.partial_cmp(b),
+                        (PrimitiveData::Name(a), PrimitiveData::Name(b)) => a.partial_cmp(b),
+                        _ => None,
+                    },
+                },
+            (Value::List(a), Value::List(b)) => {
+                for (a, b) in a.iter().zip(b.iter()) {
+                    match a.partial_cmp(b) {
+                        Some(Ordering::Equal) => continue,
+                        ordering => return ordering,
+                    }
+                }
+                Some(a.len().cmp(&b.len()))
+            },
+            _ => None,
+        }
+    }
 }
 
 
@@ -106,6 +884,19 @@ impl VMScope {
         self.vars.lock().unwrap().insert(name.to_string(), val.clone());
     }
     
+    /// Rebinds a variable in the nearest scope that defines it. Returns false if no scope does.
+    pub fn assign_value(&self, name: &str, val: &Value) -> bool {
+        let mut vars = self.vars.lock().unwrap();
+        if vars.contains_key(name) {
+            vars.insert(name.to_string(), val.clone());
+            return true;
+        }
+        match &self.parent {
+            None => false,
+            Some(parent) => parent.lock().unwrap().assign_value(name, val),
+        }
+    }
+    
     pub fn push_scope(&mut self, parent: Arc<Mutex<VMScope>>) {
         if self.child.is_none() {
             self.child = Some(Arc::new(Mutex::new(VMScope::new(
@@ -130,122 +921,1240 @@ impl VMScope {
 mod nativelib {
     use std::sync::Arc;
     use std::sync::Mutex;
-    use crate::interpreter::{VM, Value, extract_first_argname};
+    use std::io;
+    use crate::interpreter::{VM, Value, ShockError, ErrorKind, extract_first_argname};
+    
+    /// Fails with an arity error unless there are exactly `count` arguments.
+    fn expect_args(args: &[(String, Value)], count: usize, message: &str) -> Result<(), ShockError> {
+        if args.len() == count {
+            Ok(())
+        } else {
+            Err(ShockError::new(ErrorKind::Arity, message))
+        }
+    }
     
-    pub fn shock_let(args: Vec<(String, Value)>, vm: &Arc<Mutex<VM>>) -> Value {
+    pub fn shock_let(args: Vec<(String, Value)>, vm: &Arc<Mutex<VM>>) -> Result<Value, ShockError> {
         if args.len() < 2 {
-            println!("LET requires two arguments.");
-            return Value::Unit;
+            return Err(ShockError::new(ErrorKind::Arity, "LET requires two arguments."));
         }
         let var_name = extract_first_argname(&args, 0);
-    
-        let val = &args.get(1).unwrap().1;
-//        println!("LET name: {:?} val: {:?}", &var_name, &val);
+        
+        // Bind the value the name refers to now, rather than the name itself.
+        let val = resolve(&args.get(1).unwrap().1, vm)?;
         vm.lock().unwrap().curr_scope.lock().unwrap().vars.lock().unwrap().insert(var_name, val.clone());
-        val.clone()
+        Ok(val)
+    }
+    
+    /// `set x = value` changes an existing variable, which may be in an enclosing scope.
+    pub fn shock_set(args: Vec<(String, Value)>, vm: &Arc<Mutex<VM>>) -> Result<Value, ShockError> {
+        if args.len() < 2 {
+            return Err(ShockError::new(ErrorKind::Arity, "SET requires two arguments."));
+        }
+        let var_name = extract_first_argname(&args, 0);
+        let val = resolve(&args.get(1).unwrap().1, vm)?;
+        let scope = vm.lock().unwrap().get_current_scope();
+        let assigned = scope.lock().unwrap().assign_value(&var_name, &val);
+        if !assigned {
+            return Err(ShockError::new(
+                ErrorKind::UnboundName,
+                format!("Cannot SET `{}` because it is not defined.", var_name)));
+        }
+        Ok(val)
     }
-   
+    
     #[allow(unused_variables)]
-    pub fn shock_show(args: Vec<(String, Value)>, vm: &Arc<Mutex<VM>>) -> Value {
-//        println!("SHOW:");
-        for (name, value) in vm.lock().unwrap().curr_scope.lock().unwrap().vars.lock().unwrap().iter() {
-            println!("{:?}\t:\t{:?}", name, value);
+    pub fn shock_show(args: Vec<(String, Value)>, vm: &Arc<Mutex<VM>>) -> Result<Value, ShockError> {
+        let vm = vm.lock().unwrap();
+        let mut out = vm.stdout.lock().unwrap();
+        for (name, value) in vm.curr_scope.lock().unwrap().vars.lock().unwrap().iter() {
+            writeln!(out, "{:?}\t:\t{:?}", name, value).map_err(io_error)?;
         }
-        Value::Unit
+        Ok(Value::Unit)
+    }
+    
+    /// `print` writes its arguments to the VM's stdout, separated by spaces. Primitives are
+    /// written as they are typed, except strings, which are written without quotes.
+    pub fn shock_print(args: Vec<(String, Value)>, vm: &Arc<Mutex<VM>>) -> Result<Value, ShockError> {
+        let line = format_line(args, vm)?;
+        let out = vm.lock().unwrap().stdout.clone();
+        writeln!(out.lock().unwrap(), "{}", line).map_err(io_error)?;
+        Ok(Value::Unit)
+    }
+    
+    /// `eprint` is `print` to the VM's stderr.
+    pub fn shock_eprint(args: Vec<(String, Value)>, vm: &Arc<Mutex<VM>>) -> Result<Value, ShockError> {
+        let line = format_line(args, vm)?;
+        let err = vm.lock().unwrap().stderr.clone();
+        writeln!(err.lock().unwrap(), "{}", line).map_err(io_error)?;
+        Ok(Value::Unit)
+    }
+    
+    fn format_line(args: Vec<(String, Value)>, vm: &Arc<Mutex<VM>>) -> Result<String, ShockError> {
+        use crate::model::PrimitiveData;
+        let mut parts = vec![];
+        for (_, arg) in args.iter() {
+            parts.push(match resolve(arg, vm)? {
+                Value::Primitive(PrimitiveData::String(s)) => s,
+                Value::Primitive(primitive) => primitive.to_string(),
+                value => format!("{:?}", value),
+            });
+        }
+        Ok(parts.join(" "))
+    }
+    
+    fn io_error(err: io::Error) -> ShockError {
+        ShockError::new(ErrorKind::Io, format!("Could not write output: {}.", err))
     }
     
-    pub fn shock_get(args: Vec<(String, Value)>, vm: &Arc<Mutex<VM>>) -> Value {
-        if args.len() < 0 || args.len() >= 2 { return Value::Unit; }
+    pub fn shock_get(args: Vec<(String, Value)>, vm: &Arc<Mutex<VM>>) -> Result<Value, ShockError> {
+        expect_args(&args, 1, "GET requires one argument.")?;
         let var_name = extract_first_argname(&args, 0);
-        println!("GET {:?}", var_name);
-        match vm.lock().unwrap().curr_scope.lock().unwrap().lookup_value(var_name.as_ref()) {
-            None => { Value::Unit },
-            Some(val) => { val.clone() },
+        lookup(&var_name, vm)
+    }
+    
+    /// Gets the value of a variable in the current scope.
+    pub fn lookup(name: &str, vm: &Arc<Mutex<VM>>) -> Result<Value, ShockError> {
+        match vm.lock().unwrap().curr_scope.lock().unwrap().lookup_value(name) {
+            None => Err(ShockError::new(ErrorKind::UnboundName, format!("`{}` is not defined.", name))),
+            Some(val) => Ok(val),
         }
     }
     
-    fn to_float(value: &Value, vm: &Arc<Mutex<VM>>) -> f64 {
-            use crate::interpreter::nativelib::shock_get;
-            use crate::model::PrimitiveData;
-           
-            match value {
-                Value::Primitive(primitive) => match primitive {
-                    PrimitiveData::Name(v) => {
-                        let val = shock_get(
-                            vec![(v.to_string(), Value::Unit)],
-                            vm);
-                        to_float(&val, vm)
-                    },
-                    PrimitiveData::Bool(v) => if *v { 1.0 } else { 0.0 },
-                    PrimitiveData::Byte(v) => *v as f64,
-                    PrimitiveData::Int(v) => *v as f64,
-                    PrimitiveData::Float(v) => *v as f64,
-                    _ => 0.0,
+    /// Looks up what a name or path argument refers to. Any other value is returned as is.
+    pub fn resolve(value: &Value, vm: &Arc<Mutex<VM>>) -> Result<Value, ShockError> {
+        use crate::model::PrimitiveData;
+        
+        match value {
+            Value::Primitive(PrimitiveData::Name(name)) => {
+                let val = lookup(name, vm)?;
+                match &val {
+                    // A name bound to itself would never resolve any further.
+                    Value::Primitive(PrimitiveData::Name(next)) if next == name => Ok(val.clone()),
+                    _ => resolve(&val, vm),
+                }
+            },
+            Value::Path(path_components) => {
+                let looked_up = vm.lock().unwrap().lookup_path(path_components)?;
+                resolve(&looked_up, vm)
+            },
+            _ => Ok(value.clone()),
+        }
+    }
+    
+    pub mod collections {
+        use crate::interpreter::{VM, Value, ShockError, ErrorKind};
+        use crate::interpreter::nativelib::{resolve, expect_args};
+        use std::sync::{Arc, Mutex};
+        use crate::model::PrimitiveData;
+        
+        /// `length <list-or-struct-or-string>`
+        pub fn length(args: Vec<(String, Value)>, vm: &Arc<Mutex<VM>>) -> Result<Value, ShockError> {
+            expect_args(&args, 1, "LENGTH requires one argument.")?;
+            let len = match resolve(&args[0].1, vm)? {
+                Value::List(elements) => elements.len(),
+                Value::Struct(fields) => fields.len(),
+                Value::Primitive(PrimitiveData::String(s)) => s.chars().count(),
+                other => return Err(ShockError::new(
+                    ErrorKind::TypeMismatch,
+                    format!("Cannot take the length of {:?}.", other))),
+            };
+            Ok(Value::Primitive(PrimitiveData::Int(len as i64)))
+        }
+        
+        /// `index <list> <position>`, counting from 0.
+        pub fn index(args: Vec<(String, Value)>, vm: &Arc<Mutex<VM>>) -> Result<Value, ShockError> {
+            expect_args(&args, 2, "INDEX requires two arguments.")?;
+            let position = match resolve(&args[1].1, vm)? {
+                Value::Primitive(PrimitiveData::Int(i)) if i >= 0 => i as usize,
+                Value::Primitive(PrimitiveData::Unsigned(u)) => u as usize,
+                Value::Primitive(PrimitiveData::Byte(b)) => b as usize,
+                other => return Err(ShockError::new(
+                    ErrorKind::TypeMismatch,
+                    format!("Cannot index with {:?}.", other))),
+            };
+            match resolve(&args[0].1, vm)? {
+                Value::List(elements) => {
+                    let len = elements.len();
+                    elements.into_iter().nth(position).ok_or_else(|| ShockError::new(
+                        ErrorKind::OutOfBounds,
+                        format!("Index {} is out of bounds for a list of length {}.", position, len)))
                 },
-                Value::Path(path_components) => {
-                    match vm.lock().unwrap().lookup_path(path_components) {
-                        None => 0.0,
-                        Some(value) => to_float(&value, vm),
+                other => Err(ShockError::new(
+                    ErrorKind::TypeMismatch,
+                    format!("Cannot index into {:?}.", other))),
+            }
+        }
+        
+        /// `append <list> <value>...` returns a new list with the values added to the end.
+        pub fn append(args: Vec<(String, Value)>, vm: &Arc<Mutex<VM>>) -> Result<Value, ShockError> {
+            if args.is_empty() {
+                return Err(ShockError::new(ErrorKind::Arity, "APPEND requires a list."));
+            }
+            match resolve(&args[0].1, vm)? {
+                Value::List(mut elements) => {
+                    for arg in args[1..].iter() {
+                        elements.push(resolve(&arg.1, vm)?);
                     }
+                    Ok(Value::List(elements))
+                },
+                other => Err(ShockError::new(
+                    ErrorKind::TypeMismatch,
+                    format!("Cannot append to {:?}.", other))),
+            }
+        }
+        
+        /// `field <struct> <name>` gets a field of a struct by its label.
+        pub fn field(args: Vec<(String, Value)>, vm: &Arc<Mutex<VM>>) -> Result<Value, ShockError> {
+            expect_args(&args, 2, "FIELD requires two arguments.")?;
+            let label = match &args[1].1 {
+                Value::Primitive(PrimitiveData::Name(label)) |
+                Value::Primitive(PrimitiveData::String(label)) => label.clone(),
+                other => return Err(ShockError::new(
+                    ErrorKind::TypeMismatch,
+                    format!("Cannot use {:?} as a field name.", other))),
+            };
+            match resolve(&args[0].1, vm)? {
+                Value::Struct(fields) => {
+                    fields.into_iter().find(|(name, _)| *name == label)
+                        .map(|(_, value)| value)
+                        .ok_or_else(|| ShockError::new(
+                            ErrorKind::OutOfBounds,
+                            format!("The struct has no field `{}`.", label)))
                 },
-                _ => 0.0
+                other => Err(ShockError::new(
+                    ErrorKind::TypeMismatch,
+                    format!("Cannot get a field of {:?}.", other))),
             }
         }
+    }
     
-    pub mod arith {
-        use crate::interpreter::{VM, Value};
+    pub mod control {
+        use crate::interpreter::{VM, Value, ShockError, ErrorKind, Code, Step};
+        use crate::interpreter::nativelib::{resolve, expect_args};
+        use crate::parser::ExpressionValue;
+        use crate::model::PrimitiveData;
+        use std::sync::{Arc, Mutex};
+        
+        /// Only `false` and unit are false; every other value is true.
+        pub fn is_truthy(value: &Value) -> bool {
+            !matches!(value, Value::Unit | Value::Primitive(PrimitiveData::Bool(false)))
+        }
+        
+        /// Matches arguments to parameters: labeled arguments go to the parameter with the same
+        /// name, and the unlabeled ones fill the remaining parameters in order.
+        fn bind(params: &[&str], args: &[(String, Code)]) -> Vec<Option<Code>> {
+            let mut bound: Vec<Option<Code>> = params.iter()
+                .map(|param| args.iter().find(|(label, _)| label == param).map(|(_, code)| code.clone()))
+                .collect();
+            let mut positional = args.iter()
+                .filter(|(label, _)| label.is_empty())
+                .map(|(_, code)| code.clone());
+            for slot in bound.iter_mut().filter(|slot| slot.is_none()) {
+                *slot = positional.next();
+            }
+            bound
+        }
+        
+        fn condition(cond: &Option<Code>, form: &str) -> Result<Code, ShockError> {
+            cond.clone().ok_or_else(|| ShockError::new(ErrorKind::Arity, format!("{} requires a condition.", form)))
+        }
+        
+        fn required(arg: &Option<Code>, message: &str) -> Result<Code, ShockError> {
+            arg.clone().ok_or_else(|| ShockError::new(ErrorKind::Arity, message))
+        }
+        
+        fn branch(branch: &Option<Code>) -> Step {
+            match branch {
+                Some(code) => Step::Eval(code.clone()),
+                None => Step::Value(Value::Unit),
+            }
+        }
+        
+        /// `if cond: <expr> then: <expr> else: <expr>` only evaluates the branch that is taken.
+        pub fn shock_if(args: Vec<(String, Code)>, _vm: &Arc<Mutex<VM>>) -> Result<Step, ShockError> {
+            let bound = bind(&["cond", "then", "else"], &args);
+            let (then, otherwise) = (bound[1].clone(), bound[2].clone());
+            Ok(Step::then(condition(&bound[0], "IF")?, move |value, _| {
+                Ok(if is_truthy(&value) { branch(&then) } else { branch(&otherwise) })
+            }))
+        }
+        
+        /// `when cond: <expr> then: <expr>` evaluates *then* only if *cond* is true.
+        pub fn shock_when(args: Vec<(String, Code)>, _vm: &Arc<Mutex<VM>>) -> Result<Step, ShockError> {
+            let bound = bind(&["cond", "then"], &args);
+            let then = bound[1].clone();
+            Ok(Step::then(condition(&bound[0], "WHEN")?, move |value, _| {
+                Ok(if is_truthy(&value) { branch(&then) } else { Step::Value(Value::Unit) })
+            }))
+        }
+        
+        /// `unless cond: <expr> then: <expr>` evaluates *then* only if *cond* is false.
+        pub fn shock_unless(args: Vec<(String, Code)>, _vm: &Arc<Mutex<VM>>) -> Result<Step, ShockError> {
+            let bound = bind(&["cond", "then"], &args);
+            let then = bound[1].clone();
+            Ok(Step::then(condition(&bound[0], "UNLESS")?, move |value, _| {
+                Ok(if is_truthy(&value) { Step::Value(Value::Unit) } else { branch(&then) })
+            }))
+        }
+        
+        /// Evaluates the expressions from `next` on, stopping at the first one whose truthiness
+        /// is `stop_at`.
+        fn short_circuit(exprs: Arc<Vec<Code>>, next: usize, stop_at: bool) -> Step {
+            match exprs.get(next).cloned() {
+                None => Step::Value(Value::Primitive(PrimitiveData::Bool(!stop_at))),
+                Some(expr) => Step::then(expr, move |value, _| {
+                    Ok(if is_truthy(&value) == stop_at {
+                        Step::Value(Value::Primitive(PrimitiveData::Bool(stop_at)))
+                    } else {
+                        short_circuit(exprs.clone(), next + 1, stop_at)
+                    })
+                }),
+            }
+        }
+        
+        /// `and <expr>...` stops evaluating at the first false argument.
+        pub fn shock_and(args: Vec<(String, Code)>, _vm: &Arc<Mutex<VM>>) -> Result<Step, ShockError> {
+            let exprs = args.into_iter().map(|(_, code)| code).collect();
+            Ok(short_circuit(Arc::new(exprs), 0, false))
+        }
+        
+        /// `or <expr>...` stops evaluating at the first true argument.
+        pub fn shock_or(args: Vec<(String, Code)>, _vm: &Arc<Mutex<VM>>) -> Result<Step, ShockError> {
+            let exprs = args.into_iter().map(|(_, code)| code).collect();
+            Ok(short_circuit(Arc::new(exprs), 0, true))
+        }
+        
+        /// Tries the clauses from `next` on.
+        fn cond_clauses(clauses: Arc<Vec<Code>>, next: usize) -> Result<Step, ShockError> {
+            let clause = match clauses.get(next) {
+                None => return Ok(Step::Value(Value::Unit)),
+                Some(clause) => clause,
+            };
+            match clause.expr() {
+                ExpressionValue::List(parts, _) if parts.len() == 2 => {},
+                _ => return Err(ShockError::new(
+                    ErrorKind::TypeMismatch,
+                    format!("COND clauses must be lists of a test and an expression, at {}.", clause.expr().span()))),
+            }
+            let (test, expr) = (clause.child(0), clause.child(1));
+            match test.expr() {
+                ExpressionValue::Primitive(PrimitiveData::Name(name), _) if name == "else" => Ok(Step::Eval(expr)),
+                _ => Ok(Step::then(test, move |value, _| {
+                    if is_truthy(&value) {
+                        Ok(Step::Eval(expr.clone()))
+                    } else {
+                        cond_clauses(clauses.clone(), next + 1)
+                    }
+                })),
+            }
+        }
+        
+        /// `cond [<test>, <expr>]...` evaluates the expression of the first clause whose test is
+        /// true. A test of `else` always matches.
+        pub fn shock_cond(args: Vec<(String, Code)>, _vm: &Arc<Mutex<VM>>) -> Result<Step, ShockError> {
+            let clauses = args.into_iter().map(|(_, code)| code).collect();
+            cond_clauses(Arc::new(clauses), 0)
+        }
+        
+        /// `reset <expr>` limits the continuations that `shift` captures inside *expr* to the
+        /// rest of *expr*.
+        pub fn shock_reset(args: Vec<(String, Code)>, _vm: &Arc<Mutex<VM>>) -> Result<Step, ShockError> {
+            let bound = bind(&["body"], &args);
+            Ok(Step::Reset(required(&bound[0], "RESET requires an expression.")?))
+        }
+        
+        /// `shift <procedure>` calls the procedure with the continuation up to the nearest
+        /// `reset`, and the `reset` then has the value of the procedure.
+        pub fn shock_shift(args: Vec<(String, Code)>, _vm: &Arc<Mutex<VM>>) -> Result<Step, ShockError> {
+            let bound = bind(&["with"], &args);
+            let procedure = required(&bound[0], "SHIFT requires a procedure.")?;
+            Ok(Step::then(procedure, |procedure, _| Ok(Step::Shift(procedure))))
+        }
+        
+        /// `try <expr> handle: <procedure>` calls the handler with the condition of any error or
+        /// signal in *expr*, and then has the value of the handler. A signal also passes the
+        /// continuation to resume, so handlers of signals take `[condition k]`.
+        pub fn shock_try(args: Vec<(String, Code)>, _vm: &Arc<Mutex<VM>>) -> Result<Step, ShockError> {
+            let bound = bind(&["body", "handle"], &args);
+            let body = required(&bound[0], "TRY requires an expression.")?;
+            let handler = required(&bound[1], "TRY requires a handler.")?;
+            Ok(Step::then(handler, move |handler, _| Ok(Step::Try(body.clone(), handler))))
+        }
+        
+        /// `raise <value>` fails with the value as the condition. It cannot be resumed.
+        pub fn raise(args: Vec<(String, Value)>, vm: &Arc<Mutex<VM>>) -> Result<Value, ShockError> {
+            expect_args(&args, 1, "RAISE requires one argument.")?;
+            Err(ShockError::raised(resolve(&args[0].1, vm)?))
+        }
+        
+        /// `signal <condition>` suspends the computation and hands it to the nearest handler. The
+        /// signal has the value that the handler resumes it with.
+        pub fn shock_signal(args: Vec<(String, Code)>, _vm: &Arc<Mutex<VM>>) -> Result<Step, ShockError> {
+            let bound = bind(&["condition"], &args);
+            let condition = required(&bound[0], "SIGNAL requires a condition.")?;
+            Ok(Step::then(condition, |condition, _| Ok(Step::Signal(condition))))
+        }
+        
+        /// `resume <continuation> <value>` continues a suspended computation, which then has the
+        /// value as the value of its `shift` or `signal`.
+        pub fn shock_resume(args: Vec<(String, Code)>, _vm: &Arc<Mutex<VM>>) -> Result<Step, ShockError> {
+            let bound = bind(&["k", "value"], &args);
+            let value = bound[1].clone();
+            Ok(Step::then(required(&bound[0], "RESUME requires a continuation.")?, move |k, _| {
+                Ok(match &value {
+                    None => Step::Apply(k, vec![]),
+                    Some(value) => Step::then(value.clone(), move |value, _| {
+                        Ok(Step::Apply(k.clone(), vec![(String::new(), value)]))
+                    }),
+                })
+            }))
+        }
+    }
+    
+    
+    /// Natives for the graph of places in the VM's place store. Attributes hold primitive data,
+    /// or link to other places.
+    pub mod places {
+        use crate::interpreter::{VM, Value, ShockError, ErrorKind};
+        use crate::interpreter::nativelib::{resolve, expect_args};
+        use crate::primitive::types::{Place, PlaceId, AttributeData};
+        use crate::placemodel::storage::PlaceStore;
         use std::sync::{Arc, Mutex};
         use crate::model::PrimitiveData;
+        
+        /// The value of an attribute: its data, or the place it links to.
+        pub fn attribute_value(data: &AttributeData) -> Value {
+            match data {
+                AttributeData::Data(primitive) => Value::Primitive(primitive.clone()),
+                AttributeData::Place(id) | AttributeData::Reference(id) => Value::PlaceRef(*id),
+            }
+        }
+        
+        /// Attribute names are written as names, like `get_attr p size`, or as strings.
+        fn attribute_name(value: &Value) -> Result<String, ShockError> {
+            match value {
+                Value::Primitive(PrimitiveData::Name(name)) |
+                Value::Primitive(PrimitiveData::String(name)) => Ok(name.clone()),
+                other => Err(ShockError::new(
+                    ErrorKind::TypeMismatch,
+                    format!("Cannot use {:?} as an attribute name.", other))),
+            }
+        }
+        
+        /// Only primitive data can be put in an attribute. Links to places are made with `link`
+        /// and `refer`.
+        fn attribute_data(value: Value) -> Result<AttributeData, ShockError> {
+            match value {
+                Value::Primitive(primitive) => Ok(AttributeData::Data(primitive)),
+                Value::PlaceRef(_) => Err(ShockError::new(
+                    ErrorKind::TypeMismatch,
+                    "Use `link` or `refer` to put a place in an attribute.")),
+                other => Err(ShockError::new(
+                    ErrorKind::TypeMismatch,
+                    format!("Cannot put {:?} in an attribute, only primitive data.", other))),
+            }
+        }
+        
+        /// Gets a copy of the place an argument refers to.
+        fn place(value: &Value, vm: &Arc<Mutex<VM>>) -> Result<Place, ShockError> {
+            match resolve(value, vm)? {
+                Value::PlaceRef(id) => vm.lock().unwrap().places.get_place(&id).cloned().ok_or_else(|| ShockError::new(
+                    ErrorKind::MissingPlace,
+                    format!("The place {} is not in the place store.", id))),
+                other => Err(ShockError::new(
+                    ErrorKind::TypeMismatch,
+                    format!("Expected a place, but got {:?}.", other))),
+            }
+        }
+        
+        fn missing_attribute(place: &Place, name: &str) -> ShockError {
+            ShockError::new(
+                ErrorKind::OutOfBounds,
+                format!("The place {} has no attribute `{}`.", place.get_id(), name))
+        }
+        
+        /// `place` creates a place. Labeled arguments become its attributes, e.g.
+        /// `place name: "root" size: 3`.
+        pub fn shock_place(args: Vec<(String, Value)>, vm: &Arc<Mutex<VM>>) -> Result<Value, ShockError> {
+            let mut place = Place::generate_new();
+            for (label, value) in args.iter() {
+                if label.is_empty() {
+                    return Err(ShockError::new(
+                        ErrorKind::Arity,
+                        "PLACE takes only labeled arguments, which name its attributes."));
+                }
+                place.put_attr(label.clone(), attribute_data(resolve(value, vm)?)?);
+            }
+            let id = place.get_id();
+            vm.lock().unwrap().places.put_place(place)?;
+            Ok(Value::PlaceRef(id))
+        }
+        
+        pub fn get_attr(args: Vec<(String, Value)>, vm: &Arc<Mutex<VM>>) -> Result<Value, ShockError> {
+            expect_args(&args, 2, "GET_ATTR requires two arguments.")?;
+            let place = place(&args[0].1, vm)?;
+            let name = attribute_name(&args[1].1)?;
+            place.get_attr(&name).map(attribute_value).ok_or_else(|| missing_attribute(&place, &name))
+        }
+        
+        /// `put_attr p size 3` sets an attribute, and returns the value.
+        pub fn put_attr(args: Vec<(String, Value)>, vm: &Arc<Mutex<VM>>) -> Result<Value, ShockError> {
+            expect_args(&args, 3, "PUT_ATTR requires three arguments.")?;
+            let mut place = place(&args[0].1, vm)?;
+            let name = attribute_name(&args[1].1)?;
+            let value = resolve(&args[2].1, vm)?;
+            place.put_attr(name, attribute_data(value.clone())?);
+            vm.lock().unwrap().places.put_place(place)?;
+            Ok(value)
+        }
+        
+        /// Removes an attribute or link, and returns what it held, or unit if there was none.
+        pub fn remove_attr(args: Vec<(String, Value)>, vm: &Arc<Mutex<VM>>) -> Result<Value, ShockError> {
+            expect_args(&args, 2, "REMOVE_ATTR requires two arguments.")?;
+            let mut place = place(&args[0].1, vm)?;
+            let name = attribute_name(&args[1].1)?;
+            let removed = place.get_attr(&name).map_or(Value::Unit, attribute_value);
+            place.remove_attr(&name);
+            vm.lock().unwrap().places.put_place(place)?;
+            Ok(removed)
+        }
+        
+        /// Links an attribute of the first argument to the place in the third.
+        fn put_link(
+            args: Vec<(String, Value)>,
+            vm: &Arc<Mutex<VM>>,
+            command: &str,
+            data: fn(PlaceId) -> AttributeData) -> Result<Value, ShockError> {
+            expect_args(&args, 3, &format!("{} requires three arguments.", command))?;
+            let mut from = place(&args[0].1, vm)?;
+            let name = attribute_name(&args[1].1)?;
+            let to = place(&args[2].1, vm)?.get_id();
+            from.put_attr(name, data(to));
+            vm.lock().unwrap().places.put_place(from)?;
+            Ok(Value::PlaceRef(to))
+        }
+        
+        /// `link a child b` makes `a` possess `b` through the attribute `child`, and returns `b`.
+        /// A place can only have one owner.
+        pub fn link(args: Vec<(String, Value)>, vm: &Arc<Mutex<VM>>) -> Result<Value, ShockError> {
+            put_link(args, vm, "LINK", AttributeData::Place)
+        }
+        
+        /// `refer a dep b` makes the attribute `dep` of `a` refer to `b` without owning it, and
+        /// returns `b`.
+        pub fn refer(args: Vec<(String, Value)>, vm: &Arc<Mutex<VM>>) -> Result<Value, ShockError> {
+            put_link(args, vm, "REFER", AttributeData::Reference)
+        }
+        
+        /// `follow a child name` follows the link `child` of `a`, then the link `name` of the
+        /// place it leads to, and so on.
+        pub fn follow(args: Vec<(String, Value)>, vm: &Arc<Mutex<VM>>) -> Result<Value, ShockError> {
+            if args.len() < 2 {
+                return Err(ShockError::new(ErrorKind::Arity, "FOLLOW requires a place and at least one link."));
+            }
+            let mut current = place(&args[0].1, vm)?;
+            for (_, arg) in args[1..].iter() {
+                let name = attribute_name(arg)?;
+                let next: PlaceId = match current.get_attr(&name) {
+                    None => return Err(missing_attribute(&current, &name)),
+                    Some(AttributeData::Place(id)) | Some(AttributeData::Reference(id)) => *id,
+                    Some(AttributeData::Data(data)) => return Err(ShockError::new(
+                        ErrorKind::TypeMismatch,
+                        format!("The attribute `{}` of {} holds {:?}, not a link.", name, current.get_id(), data))),
+                };
+                current = place(&Value::PlaceRef(next), vm)?;
+            }
+            Ok(Value::PlaceRef(current.get_id()))
+        }
+    }
+
+    /// The commands of a structural editing session, like `focus .main.body` and `insert`. They
+    /// work on the VM's place store, relative to the focus of its editor.
+    pub mod editing {
+        use crate::interpreter::{VM, Value, ShockError, ErrorKind, FromValue};
+        use crate::interpreter::nativelib::{resolve, expect_args};
+        use crate::interpreter::nativelib::places::attribute_value;
+        use crate::editor::{self, Focus};
+        use crate::placemodel::storage::{PlaceStore, DeletePolicy};
+        use crate::primitive::types::{PlaceId, AttributeData};
+        use crate::model::PrimitiveData;
+        use std::collections::HashMap;
+        use std::sync::{Arc, Mutex};
+        
+        /// Paths are written like `.main.body`, and a single step can also be a name.
+        fn path(value: &Value) -> Result<Vec<String>, ShockError> {
+            match value {
+                Value::Path(components) => Ok(components.clone()),
+                Value::Primitive(PrimitiveData::Name(name)) |
+                Value::Primitive(PrimitiveData::String(name)) => Ok(vec![name.clone()]),
+                other => Err(ShockError::new(
+                    ErrorKind::TypeMismatch,
+                    format!("Expected a path, but got {:?}.", other))),
+            }
+        }
+        
+        /// The argument with the label, or else the first unlabeled one.
+        fn argument<'a>(args: &'a [(String, Value)], label: &str) -> Option<&'a Value> {
+            args.iter().find(|(name, _)| name == label)
+                .or_else(|| args.iter().find(|(name, _)| name.is_empty()))
+                .map(|(_, value)| value)
+        }
+        
+        fn invalid_edit<T>(message: String) -> Result<T, ShockError> {
+            Err(ShockError::new(ErrorKind::InvalidEdit, message))
+        }
+        
+        /// `focus .main.body` focuses on a place, and `focus ..` on the one above.
+        pub fn focus(args: Vec<(String, Value)>, vm: &Arc<Mutex<VM>>) -> Result<Value, ShockError> {
+            expect_args(&args, 1, "FOCUS requires a path.")?;
+            let path = path(&args[0].1)?;
+            let mut vm = vm.lock().unwrap();
+            let vm = &mut *vm;
+            let focus = vm.editor.resolve(&vm.places, &path)?;
+            vm.editor.focus_on(focus);
+            Ok(Value::PlaceRef(vm.editor.current()))
+        }
+        
+        /// `unfocus` goes back to where the last `focus` came from.
+        pub fn unfocus(args: Vec<(String, Value)>, vm: &Arc<Mutex<VM>>) -> Result<Value, ShockError> {
+            expect_args(&args, 0, "UNFOCUS takes no arguments.")?;
+            let mut vm = vm.lock().unwrap();
+            let vm = &mut *vm;
+            vm.editor.unfocus(&vm.places)?;
+            Ok(Value::PlaceRef(vm.editor.current()))
+        }
+        
+        /// `list` writes the focused place, or the place at a path, to the VM's stdout.
+        pub fn list(args: Vec<(String, Value)>, vm: &Arc<Mutex<VM>>) -> Result<Value, ShockError> {
+            let path = match args.len() {
+                0 => vec![],
+                1 => path(&args[0].1)?,
+                _ => return Err(ShockError::new(ErrorKind::Arity, "LIST takes at most one path.")),
+            };
+            let vm = vm.lock().unwrap();
+            let focus = vm.editor.resolve(&vm.places, &path)?;
+            let id = focus.last().map_or(vm.editor.root(), |(_, id)| *id);
+            let place = editor::get_place(&vm.places, id)?;
+            let listing = editor::list(&vm.places, &vm.editor.describe(&focus), place);
+            writeln!(vm.stdout.lock().unwrap(), "{}", listing)
+                .map_err(|err| ShockError::new(ErrorKind::Io, format!("Could not write output: {}.", err)))?;
+            Ok(Value::Unit)
+        }
+        
+        /// `create Procedure at: main` makes a place of a type under the focus, or at a path like
+        /// `.main.locals`. Other labeled arguments become its attributes, which refer to any
+        /// places they are given.
+        pub fn create(args: Vec<(String, Value)>, vm: &Arc<Mutex<VM>>) -> Result<Value, ShockError> {
+            let kind = match argument(&args, "type") {
+                Some(Value::Primitive(PrimitiveData::Name(kind))) |
+                Some(Value::Primitive(PrimitiveData::String(kind))) => kind.clone(),
+                Some(other) => return Err(ShockError::new(
+                    ErrorKind::TypeMismatch,
+                    format!("Expected a type name, but got {:?}.", other))),
+                None => return Err(ShockError::new(ErrorKind::Arity, "CREATE requires a type.")),
+            };
+            let mut at = match args.iter().find(|(label, _)| label == "at") {
+                Some((_, value)) => path(value)?,
+                None => return Err(ShockError::new(ErrorKind::Arity, "CREATE requires a name to create the place `at:`.")),
+            };
+            let name = match at.pop() {
+                Some(ref name) if !name.is_empty() => name.clone(),
+                _ => return invalid_edit("The path to create a place at must end in a name.".to_owned()),
+            };
+            let mut attributes = HashMap::new();
+            for (label, value) in args.iter() {
+                if !label.is_empty() && label != "type" && label != "at" {
+                    match resolve(value, vm)? {
+                        Value::Primitive(data) => attributes.insert(label.clone(), AttributeData::Data(data)),
+                        Value::PlaceRef(id) => attributes.insert(label.clone(), AttributeData::Reference(id)),
+                        other => return Err(ShockError::new(
+                            ErrorKind::TypeMismatch,
+                            format!("Cannot put {:?} in an attribute.", other))),
+                    };
+                }
+            }
+            
+            let mut vm = vm.lock().unwrap();
+            let vm = &mut *vm;
+            let parent: Focus = vm.editor.walk(&vm.places, &at)?;
+            let parent_id = parent.last().map_or(vm.editor.root(), |(_, id)| *id);
+            let mut parent_place = editor::get_place(&vm.places, parent_id)?.clone();
+            if parent_place.contains_key(&name) {
+                return invalid_edit(format!("`{}` already has `{}`.", vm.editor.describe(&parent), name));
+            }
+            let id = vm.places.transact(|places| {
+                let id = editor::create(places, &kind, attributes)?;
+                parent_place.put_attr(name, AttributeData::Place(id));
+                places.put_place(parent_place)?;
+                Ok(id)
+            })?;
+            Ok(Value::PlaceRef(id))
+        }
+        
+        /// `insert "text"` adds a value to the end of the focused list, and `insert item: x at: 0`
+        /// adds it at a position. Places are referred to, and primitive data is stored as is.
+        pub fn insert(args: Vec<(String, Value)>, vm: &Arc<Mutex<VM>>) -> Result<Value, ShockError> {
+            let item = match argument(&args, "item") {
+                Some(item) => resolve(item, vm)?,
+                None => return Err(ShockError::new(ErrorKind::Arity, "INSERT requires an item.")),
+            };
+            let data = match &item {
+                Value::Primitive(data) => AttributeData::Data(data.clone()),
+                Value::PlaceRef(id) => AttributeData::Reference(*id),
+                other => return Err(ShockError::new(
+                    ErrorKind::TypeMismatch,
+                    format!("Cannot insert {:?} into a place.", other))),
+            };
+            let position = match args.iter().find(|(label, _)| label == "at") {
+                None => None,
+                Some((_, at)) => Some(usize::from_value(resolve(at, vm)?)?),
+            };
+            
+            let mut vm = vm.lock().unwrap();
+            let vm = &mut *vm;
+            let mut list = editor::get_place(&vm.places, vm.editor.current())?.clone();
+            if editor::type_of(&list) != "List" {
+                return invalid_edit(format!(
+                    "Can only insert into a List, but `{}` is a {}.",
+                    vm.editor.describe(vm.editor.focus()), editor::type_of(&list)));
+            }
+            let mut elements = editor::elements(&list);
+            let position = position.unwrap_or(elements.len());
+            if position > elements.len() {
+                return Err(ShockError::new(
+                    ErrorKind::OutOfBounds,
+                    format!("Cannot insert at {} into a list of {}.", position, elements.len())));
+            }
+            elements.insert(position, data);
+            editor::set_elements(&mut list, elements);
+            vm.places.put_place(list)?;
+            Ok(item)
+        }
+        
+        /// `remove at: 0` removes an element of the focused list, and `remove at: .main` removes
+        /// the place at a path. Removing a possession deletes the place and everything it
+        /// possesses, and removing a reference only unlinks it. Returns what was removed.
+        pub fn remove(args: Vec<(String, Value)>, vm: &Arc<Mutex<VM>>) -> Result<Value, ShockError> {
+            expect_args(&args, 1, "REMOVE requires a position or a path to remove `at:`.")?;
+            let at = match &args[0].1 {
+                Value::Path(_) => args[0].1.clone(),
+                other => resolve(other, vm)?,
+            };
+            
+            let mut vm = vm.lock().unwrap();
+            let vm = &mut *vm;
+            let (changed, removed) = match at {
+                Value::Primitive(number @ PrimitiveData::Int(_)) |
+                Value::Primitive(number @ PrimitiveData::Unsigned(_)) |
+                Value::Primitive(number @ PrimitiveData::Byte(_)) => {
+                    let mut list = editor::get_place(&vm.places, vm.editor.current())?.clone();
+                    if editor::type_of(&list) != "List" {
+                        return invalid_edit(format!(
+                            "Can only remove elements from a List, but `{}` is a {}.",
+                            vm.editor.describe(vm.editor.focus()), editor::type_of(&list)));
+                    }
+                    let mut elements = editor::elements(&list);
+                    let position = match usize::from_value(Value::Primitive(number.clone())) {
+                        Ok(position) if position < elements.len() => position,
+                        _ => return Err(ShockError::new(
+                            ErrorKind::OutOfBounds,
+                            format!("`{}` has no element {}.", vm.editor.describe(vm.editor.focus()), number))),
+                    };
+                    let removed = elements.remove(position);
+                    editor::set_elements(&mut list, elements);
+                    (list, removed)
+                },
+                at => {
+                    let target = vm.editor.resolve(&vm.places, &path(&at)?)?;
+                    let (name, id) = match target.last() {
+                        Some((_, id)) if vm.editor.is_focused(*id) => return invalid_edit(format!(
+                            "Cannot remove `{}` while it is focused.", vm.editor.describe(&target))),
+                        Some(last) => last.clone(),
+                        None => return invalid_edit("Cannot remove the root `$`.".to_owned()),
+                    };
+                    let parent_id = target[..target.len() - 1].last().map_or(vm.editor.root(), |(_, id)| *id);
+                    let mut parent = editor::get_place(&vm.places, parent_id)?.clone();
+                    let removed = parent.get_attr(&name).cloned().unwrap_or(AttributeData::Place(id));
+                    parent.remove_attr(&name);
+                    (parent, removed)
+                },
+            };
+            vm.places.transact(|places| {
+                places.put_place(changed)?;
+                if let AttributeData::Place(id) = removed {
+                    places.delete_place_with(&id, DeletePolicy::Cascade)?;
+                }
+                Ok(())
+            })?;
+            Ok(attribute_value(&removed))
+        }
+        
+        /// The place a `freeze` or `thaw` is `at:`, and whether it is for the whole `tree:` the
+        /// place possesses.
+        fn freezing_target(
+            args: &[(String, Value)],
+            vm: &Arc<Mutex<VM>>,
+            command: &str) -> Result<(PlaceId, bool), ShockError> {
+            let at = match argument(args, "at") {
+                Some(at) => path(at)?,
+                None => return Err(ShockError::new(ErrorKind::Arity, format!("{} requires a path to a place.", command))),
+            };
+            let tree = match args.iter().find(|(label, _)| label == "tree") {
+                None => false,
+                Some((_, tree)) => match resolve(tree, vm)? {
+                    Value::Primitive(PrimitiveData::Bool(tree)) => tree,
+                    other => return Err(ShockError::new(
+                        ErrorKind::TypeMismatch,
+                        format!("Expected `tree:` to be a Bool, but got {:?}.", other))),
+                },
+            };
+            let vm = vm.lock().unwrap();
+            let target = vm.editor.resolve(&vm.places, &at)?;
+            Ok((target.last().map_or(vm.editor.root(), |(_, id)| *id), tree))
+        }
+        
+        /// `freeze at: .main` makes a place immutable to the VM's context, and `tree: true` also
+        /// freezes every place it possesses. Freezing is idempotent.
+        pub fn freeze(args: Vec<(String, Value)>, vm: &Arc<Mutex<VM>>) -> Result<Value, ShockError> {
+            let (id, tree) = freezing_target(&args, vm, "FREEZE")?;
+            let mut vm = vm.lock().unwrap();
+            if tree {
+                vm.places.freeze_tree(&id)?;
+            } else {
+                vm.places.freeze(&id)?;
+            }
+            Ok(Value::PlaceRef(id))
+        }
+        
+        /// `thaw at: .main` makes a frozen place mutable again, like `freeze`. Thawing is
+        /// idempotent.
+        pub fn thaw(args: Vec<(String, Value)>, vm: &Arc<Mutex<VM>>) -> Result<Value, ShockError> {
+            let (id, tree) = freezing_target(&args, vm, "THAW")?;
+            let mut vm = vm.lock().unwrap();
+            if tree {
+                vm.places.thaw_tree(&id)?;
+            } else {
+                vm.places.thaw(&id);
+            }
+            Ok(Value::PlaceRef(id))
+        }
+    }
     
+    pub mod comparison {
+        use crate::interpreter::{VM, Value, ShockError, ErrorKind};
+        use crate::interpreter::nativelib::resolve;
+        use crate::interpreter::nativelib::control::is_truthy;
+        use crate::model::PrimitiveData;
+        use std::sync::{Arc, Mutex};
+        use std::cmp::Ordering;
         
+        /// Checks that every pair of neighbouring arguments is related, so that `< a b c` means
+        /// `a < b` and `b < c`.
+        fn chain(
+            symbol: &str,
+            args: Vec<(String, Value)>,
+            vm: &Arc<Mutex<VM>>,
+            related: fn(&Value, &Value) -> Option<bool>) -> Result<Value, ShockError> {
+            if args.len() < 2 {
+                return Err(ShockError::new(
+                    ErrorKind::Arity,
+                    format!("`{}` requires at least two arguments.", symbol)));
+            }
+            let mut values = Vec::with_capacity(args.len());
+            for (_, arg) in args.iter() {
+                values.push(resolve(arg, vm)?);
+            }
+            for pair in values.windows(2) {
+                match related(&pair[0], &pair[1]) {
+                    Some(true) => continue,
+                    Some(false) => return Ok(Value::Primitive(PrimitiveData::Bool(false))),
+                    None => return Err(ShockError::new(
+                        ErrorKind::TypeMismatch,
+                        format!("`{}` cannot compare {:?} and {:?}.", symbol, pair[0], pair[1]))),
+                }
+            }
+            Ok(Value::Primitive(PrimitiveData::Bool(true)))
+        }
+        
+        macro_rules! comparison_impl {
+            ($name: ident, $symbol: expr, |$a: ident, $b: ident| $related: expr) => {
+                pub fn $name(args: Vec<(String, Value)>, vm: &Arc<Mutex<VM>>) -> Result<Value, ShockError> {
+                    chain($symbol, args, vm, |$a, $b| $related)
+                }
+            }
+        }
+        
+        comparison_impl!(eq, "==", |a, b| Some(a == b));
+        comparison_impl!(ne, "!=", |a, b| Some(a != b));
+        comparison_impl!(lt, "<", |a, b| a.partial_cmp(b).map(|o| o == Ordering::Less));
+        comparison_impl!(le, "<=", |a, b| a.partial_cmp(b).map(|o| o != Ordering::Greater));
+        comparison_impl!(gt, ">", |a, b| a.partial_cmp(b).map(|o| o == Ordering::Greater));
+        comparison_impl!(ge, ">=", |a, b| a.partial_cmp(b).map(|o| o != Ordering::Less));
+        
+        pub fn not(value: Value) -> bool {
+            !is_truthy(&value)
+        }
+    }
+
+    pub mod arith {
+        use crate::interpreter::{VM, Value, ShockError, ErrorKind};
+        use crate::interpreter::nativelib::resolve;
+        use std::sync::{Arc, Mutex};
+        use std::cmp::Ordering;
+        use crate::model::PrimitiveData;
+        
+        /// The numeric tower, from narrowest to widest. Operands are widened to the wider of
+        /// the two kinds before an operation, and a Float operand makes the result a Float.
+        #[derive(Debug, Clone, Copy, PartialEq)]
+        pub enum Number {
+            Byte(u8),
+            Unsigned(u64),
+            Int(i64),
+            Float(f64),
+        }
+        
+        impl Number {
+            pub fn from_value(value: &Value) -> Option<Number> {
+                match value {
+                    Value::Primitive(primitive) => Number::from_primitive(primitive),
+                    _ => None,
+                }
+            }
+            
+            pub fn from_primitive(primitive: &PrimitiveData) -> Option<Number> {
+                match primitive {
+                    PrimitiveData::Byte(v) => Some(Number::Byte(*v)),
+                    PrimitiveData::Unsigned(v) => Some(Number::Unsigned(*v)),
+                    PrimitiveData::Int(v) => Some(Number::Int(*v)),
+                    PrimitiveData::Float(v) => Some(Number::Float(*v)),
+                    _ => None,
+                }
+            }
+            
+            /// Compares numbers of any kinds by value. Integers are compared exactly; only a
+            /// Float operand makes the comparison go through f64.
+            pub fn compare(self, other: Number) -> Option<Ordering> {
+                match (self.to_i128(), other.to_i128()) {
+                    (Some(a), Some(b)) => Some(a.cmp(&b)),
+                    _ => self.to_f64().partial_cmp(&other.to_f64()),
+                }
+            }
+            
+            fn to_i128(self) -> Option<i128> {
+                match self {
+                    Number::Byte(v) => Some(v as i128),
+                    Number::Unsigned(v) => Some(v as i128),
+                    Number::Int(v) => Some(v as i128),
+                    Number::Float(_) => None,
+                }
+            }
+            
+            pub fn into_value(self) -> Value {
+                Value::Primitive(match self {
+                    Number::Byte(v) => PrimitiveData::Byte(v),
+                    Number::Unsigned(v) => PrimitiveData::Unsigned(v),
+                    Number::Int(v) => PrimitiveData::Int(v),
+                    Number::Float(v) => PrimitiveData::Float(v),
+                })
+            }
+            
+            /// The zero of the same kind.
+            fn zero(&self) -> Number {
+                match self {
+                    Number::Byte(_) => Number::Byte(0),
+                    Number::Unsigned(_) => Number::Unsigned(0),
+                    Number::Int(_) => Number::Int(0),
+                    Number::Float(_) => Number::Float(0.0),
+                }
+            }
+            
+            fn rank(&self) -> u8 {
+                match self {
+                    Number::Byte(_) => 0,
+                    Number::Unsigned(_) => 1,
+                    Number::Int(_) => 2,
+                    Number::Float(_) => 3,
+                }
+            }
+            
+            fn to_f64(self) -> f64 {
+                match self {
+                    Number::Byte(v) => v as f64,
+                    Number::Unsigned(v) => v as f64,
+                    Number::Int(v) => v as f64,
+                    Number::Float(v) => v,
+                }
+            }
+            
+            /// Converts to the kind of `other` if it is wider. Fails if the value does not fit,
+            /// e.g. an Unsigned above `i64::MAX` widened to Int.
+            fn widen_to(self, other: &Number) -> Option<Number> {
+                if self.rank() >= other.rank() {
+                    return Some(self);
+                }
+                match (self, other) {
+                    (_, Number::Float(_)) => Some(Number::Float(self.to_f64())),
+                    (Number::Byte(v), Number::Unsigned(_)) => Some(Number::Unsigned(v as u64)),
+                    (Number::Byte(v), Number::Int(_)) => Some(Number::Int(v as i64)),
+                    (Number::Unsigned(v), Number::Int(_)) if v <= i64::MAX as u64 =>
+                        Some(Number::Int(v as i64)),
+                    _ => None,
+                }
+            }
+        }
+        
+        #[derive(Debug, Clone, Copy, PartialEq)]
+        pub enum Op {
+            Add,
+            Sub,
+            Mult,
+            Div,
+            FloorDiv,
+            Rem,
+        }
+        
+        impl Op {
+            fn symbol(&self) -> &'static str {
+                match self {
+                    Op::Add => "+",
+                    Op::Sub => "-",
+                    Op::Mult => "*",
+                    Op::Div => "/",
+                    Op::FloorDiv => "//",
+                    Op::Rem => "%",
+                }
+            }
+        }
+        
+        /// Byte and Unsigned operands can never be negative, so their division already rounds
+        /// down.
+        macro_rules! unsigned_op {
+            ($op: expr, $a: expr, $b: expr) => {
+                match $op {
+                    Op::Add => $a.checked_add($b),
+                    Op::Sub => $a.checked_sub($b),
+                    Op::Mult => $a.checked_mul($b),
+                    Op::FloorDiv => $a.checked_div($b),
+                    Op::Rem => $a.checked_rem($b),
+                    Op::Div => unreachable!(),
+                }
+            }
+        }
+        
+        /// Int division rounds towards negative infinity, and the remainder takes the sign of
+        /// the divisor, so that `a == (// a b) * b + (% a b)`.
+        fn int_op(op: Op, a: i64, b: i64) -> Option<i64> {
+            match op {
+                Op::Add => a.checked_add(b),
+                Op::Sub => a.checked_sub(b),
+                Op::Mult => a.checked_mul(b),
+                Op::FloorDiv => a.checked_div(b).map(|q| {
+                    if a % b != 0 && ((a < 0) != (b < 0)) { q - 1 } else { q }
+                }),
+                Op::Rem => a.checked_rem(b).map(|r| {
+                    if r != 0 && ((r < 0) != (b < 0)) { r + b } else { r }
+                }),
+                Op::Div => unreachable!(),
+            }
+        }
+        
+        /// Applies a binary operation, returning an error message on overflow, division by
+        /// zero or incompatible operands.
+        pub fn apply(op: Op, a: Number, b: Number) -> Result<Number, String> {
+            let (a, b) = match (a.widen_to(&b), b.widen_to(&a)) {
+                (Some(a), Some(b)) => (a, b),
+                _ => return Err(format!("Cannot apply `{}` to {:?} and {:?}.", op.symbol(), a, b)),
+            };
+            let overflow = || format!("Arithmetic overflow in `{}` on {:?} and {:?}.", op.symbol(), a, b);
+            
+            if let (Number::Float(a), Number::Float(b)) = (a, b) {
+                return Ok(Number::Float(match op {
+                    Op::Add => a + b,
+                    Op::Sub => a - b,
+                    Op::Mult => a * b,
+                    Op::Div => a / b,
+                    Op::FloorDiv => (a / b).floor(),
+                    Op::Rem => a - b * (a / b).floor(),
+                }));
+            }
+            
+            let divides = op == Op::Div || op == Op::FloorDiv || op == Op::Rem;
+            if divides && b.to_f64() == 0.0 {
+                return Err(format!("Division by zero in `{}`.", op.symbol()));
+            }
+            if op == Op::Div {
+                // True division of integers is not exact in general, so it always gives a Float.
+                return Ok(Number::Float(a.to_f64() / b.to_f64()));
+            }
+            
+            match (a, b) {
+                (Number::Byte(a), Number::Byte(b)) =>
+                    unsigned_op!(op, a, b).map(Number::Byte).ok_or_else(overflow),
+                (Number::Unsigned(a), Number::Unsigned(b)) =>
+                    unsigned_op!(op, a, b).map(Number::Unsigned).ok_or_else(overflow),
+                (Number::Int(a), Number::Int(b)) =>
+                    int_op(op, a, b).map(Number::Int).ok_or_else(overflow),
+                _ => unreachable!(),
+            }
+        }
+        
+        /// Folds the operation over all arguments from left to right.
+        fn fold(op: Op, args: Vec<(String, Value)>, vm: &Arc<Mutex<VM>>) -> Result<Value, ShockError> {
+            let mut numbers = Vec::with_capacity(args.len());
+            for (_, arg) in args.iter() {
+                let value = resolve(arg, vm)?;
+                match Number::from_value(&value) {
+                    Some(number) => numbers.push(number),
+                    None => return Err(ShockError::new(
+                        ErrorKind::TypeMismatch,
+                        format!("`{}` expects numbers, but got {:?}.", op.symbol(), value))),
+                }
+            }
+            let arithmetic = |message| ShockError::new(ErrorKind::Arithmetic, message);
+            
+            let mut numbers = numbers.into_iter();
+            let mut acc = match (op, numbers.next()) {
+                (Op::Add, None) => return Ok(Value::Primitive(PrimitiveData::Int(0))),
+                (Op::Mult, None) => return Ok(Value::Primitive(PrimitiveData::Int(1))),
+                (_, None) => return Err(ShockError::new(
+                    ErrorKind::Arity,
+                    format!("`{}` requires at least one argument.", op.symbol()))),
+                // A single argument to `-` is negated within its own kind, so only zero can be
+                // negated as a Byte or Unsigned.
+                (Op::Sub, Some(first)) if args.len() == 1 => {
+                    return apply(Op::Sub, first.zero(), first)
+                        .map(Number::into_value)
+                        .map_err(arithmetic);
+                },
+                (_, Some(first)) => first,
+            };
+            for number in numbers {
+                acc = apply(op, acc, number).map_err(arithmetic)?;
+            }
+            Ok(acc.into_value())
+        }
         
         macro_rules! numeric_op_impl {
-            ($name: ident, $op: tt) => {
-                pub fn $name(args: Vec<(String, Value)>, vm: &Arc<Mutex<VM>>) -> Value {
-                    use crate::interpreter::nativelib::to_float;
-                
-                    if args.len() == 0 { return Value::Primitive(PrimitiveData::Int(0)) }
-                    let (first, rest) = args.split_at(1);
-                    println!("first: {:?}, rest: {:?}", first, rest);
-                    let mut acc = to_float(&first[0].1, vm);
-                    for expr in rest {
-                        acc = acc $op to_float(&expr.1, vm);
-                    }
-                    
-                    if acc.is_normal() && math::round::ceil(acc, 0) == math::round::floor(acc, 0) {
-                        Value::Primitive(PrimitiveData::Int(acc as i64))
-                    } else {
-                        Value::Primitive(PrimitiveData::Float(acc))
-                    }
+            ($name: ident, $op: expr) => {
+                pub fn $name(args: Vec<(String, Value)>, vm: &Arc<Mutex<VM>>) -> Result<Value, ShockError> {
+                    fold($op, args, vm)
                 }
             }
         }
         
-        numeric_op_impl!(add, +);
-        numeric_op_impl!(sub, -);
-        numeric_op_impl!(mult, *);
-        numeric_op_impl!(div, /);
-        numeric_op_impl!(modulo, %);
+        numeric_op_impl!(add, Op::Add);
+        numeric_op_impl!(sub, Op::Sub);
+        numeric_op_impl!(mult, Op::Mult);
+        numeric_op_impl!(div, Op::Div);
+        numeric_op_impl!(floor_div, Op::FloorDiv);
+        numeric_op_impl!(modulo, Op::Rem);
     }
 }
 
+/// Where the output of a VM goes, e.g. `io::stdout()` or a buffer in the host application.
+pub type Sink = Arc<Mutex<dyn Write + Send>>;
+
+/// An observer shared between a VM and the host that installed it.
+pub type Observer = Arc<Mutex<dyn EvalObserver>>;
+
 pub struct VM {
     pub curr_scope: Arc<Mutex<VMScope>>,
     pub curr_expr: ExpressionValue,
+    pub stdout: Sink,
+    pub stderr: Sink,
+    /// Gets told what evaluations that start after it is installed are doing.
+    pub observer: Option<Observer>,
+    /// The graph of places that scripts work on, as the VM's context sees it.
+    pub places: Context<Box<dyn PlaceStore + Send>>,
+    /// Where the editing commands are in the place graph.
+    pub editor: Editor,
 }
 
 impl VM {
+    /// Creates a VM with an empty global scope that writes to the process's stdout and stderr,
+    /// and a place store with only the root module in it.
+    pub fn new() -> VM {
+        let mut places = HashMapPlaceStore::new();
+        let editor = Editor::new(&mut places);
+        VM {
+            curr_scope: Arc::new(Mutex::new(VMScope::new(None))),
+            curr_expr: ExpressionValue::Unit,
+            stdout: Arc::new(Mutex::new(io::stdout())),
+            stderr: Arc::new(Mutex::new(io::stderr())),
+            observer: None,
+            places: Context::new(Box::new(places)),
+            editor,
+        }
+    }
+    
     pub fn define_standard_functions(&mut self) {
         let scope = self.curr_scope.lock().unwrap();
         let mut bindings = scope.vars.lock().unwrap();
         bindings.insert("let".to_owned(), Value::NativeProcedure(NativeProcedure::new (nativelib::shock_let)));
+        bindings.insert("set".to_owned(), Value::NativeProcedure(NativeProcedure::new (nativelib::shock_set)));
         bindings.insert("show".to_owned(), Value::NativeProcedure(NativeProcedure::new (nativelib::shock_show)));
         bindings.insert("get".to_owned(), Value::NativeProcedure(NativeProcedure::new (nativelib::shock_get)));
+        bindings.insert("print".to_owned(), Value::NativeProcedure(NativeProcedure::new (nativelib::shock_print)));
+        bindings.insert("eprint".to_owned(), Value::NativeProcedure(NativeProcedure::new (nativelib::shock_eprint)));
         bindings.insert("+".to_owned(), Value::NativeProcedure(NativeProcedure::new (nativelib::arith::add)));
         bindings.insert("-".to_owned(), Value::NativeProcedure(NativeProcedure::new (nativelib::arith::sub)));
         bindings.insert("*".to_owned(), Value::NativeProcedure(NativeProcedure::new (nativelib::arith::mult)));
         bindings.insert("/".to_owned(), Value::NativeProcedure(NativeProcedure::new (nativelib::arith::div)));
+        bindings.insert("//".to_owned(), Value::NativeProcedure(NativeProcedure::new (nativelib::arith::floor_div)));
         bindings.insert("%".to_owned(), Value::NativeProcedure(NativeProcedure::new (nativelib::arith::modulo)));
+        bindings.insert("length".to_owned(), Value::NativeProcedure(NativeProcedure::new (nativelib::collections::length)));
+        bindings.insert("index".to_owned(), Value::NativeProcedure(NativeProcedure::new (nativelib::collections::index)));
+        bindings.insert("append".to_owned(), Value::NativeProcedure(NativeProcedure::new (nativelib::collections::append)));
+        bindings.insert("field".to_owned(), Value::NativeProcedure(NativeProcedure::new (nativelib::collections::field)));
+        bindings.insert("==".to_owned(), Value::NativeProcedure(NativeProcedure::new (nativelib::comparison::eq)));
+        bindings.insert("!=".to_owned(), Value::NativeProcedure(NativeProcedure::new (nativelib::comparison::ne)));
+        bindings.insert("<".to_owned(), Value::NativeProcedure(NativeProcedure::new (nativelib::comparison::lt)));
+        bindings.insert("<=".to_owned(), Value::NativeProcedure(NativeProcedure::new (nativelib::comparison::le)));
+        bindings.insert(">".to_owned(), Value::NativeProcedure(NativeProcedure::new (nativelib::comparison::gt)));
+        bindings.insert(">=".to_owned(), Value::NativeProcedure(NativeProcedure::new (nativelib::comparison::ge)));
+        bindings.insert("not".to_owned(), Value::NativeProcedure(nativelib::comparison::not.into_native()));
+        bindings.insert("if".to_owned(), Value::SpecialForm(SpecialForm::new (nativelib::control::shock_if)));
+        bindings.insert("when".to_owned(), Value::SpecialForm(SpecialForm::new (nativelib::control::shock_when)));
+        bindings.insert("unless".to_owned(), Value::SpecialForm(SpecialForm::new (nativelib::control::shock_unless)));
+        bindings.insert("and".to_owned(), Value::SpecialForm(SpecialForm::new (nativelib::control::shock_and)));
+        bindings.insert("or".to_owned(), Value::SpecialForm(SpecialForm::new (nativelib::control::shock_or)));
+        bindings.insert("cond".to_owned(), Value::SpecialForm(SpecialForm::new (nativelib::control::shock_cond)));
+        bindings.insert("place".to_owned(), Value::NativeProcedure(NativeProcedure::new (nativelib::places::shock_place)));
+        bindings.insert("get_attr".to_owned(), Value::NativeProcedure(NativeProcedure::new (nativelib::places::get_attr)));
+        bindings.insert("put_attr".to_owned(), Value::NativeProcedure(NativeProcedure::new (nativelib::places::put_attr)));
+        bindings.insert("remove_attr".to_owned(), Value::NativeProcedure(NativeProcedure::new (nativelib::places::remove_attr)));
+        bindings.insert("link".to_owned(), Value::NativeProcedure(NativeProcedure::new (nativelib::places::link)));
+        bindings.insert("refer".to_owned(), Value::NativeProcedure(NativeProcedure::new (nativelib::places::refer)));
+        bindings.insert("follow".to_owned(), Value::NativeProcedure(NativeProcedure::new (nativelib::places::follow)));
+        bindings.insert("create".to_owned(), Value::NativeProcedure(NativeProcedure::new (nativelib::editing::create)));
+        bindings.insert("focus".to_owned(), Value::NativeProcedure(NativeProcedure::new (nativelib::editing::focus)));
+        bindings.insert("unfocus".to_owned(), Value::NativeProcedure(NativeProcedure::new (nativelib::editing::unfocus)));
+        bindings.insert("list".to_owned(), Value::NativeProcedure(NativeProcedure::new (nativelib::editing::list)));
+        bindings.insert("insert".to_owned(), Value::NativeProcedure(NativeProcedure::new (nativelib::editing::insert)));
+        bindings.insert("remove".to_owned(), Value::NativeProcedure(NativeProcedure::new (nativelib::editing::remove)));
+        bindings.insert("freeze".to_owned(), Value::NativeProcedure(NativeProcedure::new (nativelib::editing::freeze)));
+        bindings.insert("thaw".to_owned(), Value::NativeProcedure(NativeProcedure::new (nativelib::editing::thaw)));
+        // The short forms from docs/actions.md and the parser's keywords.
+        bindings.insert("cre".to_owned(), Value::NativeProcedure(NativeProcedure::new (nativelib::editing::create)));
+        bindings.insert("fs".to_owned(), Value::NativeProcedure(NativeProcedure::new (nativelib::editing::focus)));
+        bindings.insert("in".to_owned(), Value::NativeProcedure(NativeProcedure::new (nativelib::editing::insert)));
+        bindings.insert("rm".to_owned(), Value::NativeProcedure(NativeProcedure::new (nativelib::editing::remove)));
+        bindings.insert("fr".to_owned(), Value::NativeProcedure(NativeProcedure::new (nativelib::editing::freeze)));
+        bindings.insert("th".to_owned(), Value::NativeProcedure(NativeProcedure::new (nativelib::editing::thaw)));
+        bindings.insert("reset".to_owned(), Value::SpecialForm(SpecialForm::new (nativelib::control::shock_reset)));
+        bindings.insert("shift".to_owned(), Value::SpecialForm(SpecialForm::new (nativelib::control::shock_shift)));
+        bindings.insert("try".to_owned(), Value::SpecialForm(SpecialForm::new (nativelib::control::shock_try)));
+        bindings.insert("raise".to_owned(), Value::NativeProcedure(NativeProcedure::new (nativelib::control::raise)));
+        bindings.insert("signal".to_owned(), Value::SpecialForm(SpecialForm::new (nativelib::control::shock_signal)));
+        bindings.insert("resume".to_owned(), Value::SpecialForm(SpecialForm::new (nativelib::control::shock_resume)));
+    }
+
+    /// Defines a Rust function or closure as a native in the current scope. Its arguments are
+    /// checked and converted with `FromValue`, and its result with `IntoValue`, e.g.
+    /// `vm.register("add", |a: i64, b: i64| a + b)`.
+    pub fn register<Args, F: IntoNative<Args>>(&mut self, name: &str, f: F) {
+        let native = Value::NativeProcedure(f.into_native());
+        self.curr_scope.lock().unwrap().set_value(name, &native);
     }
 
     pub fn push_scope(&mut self) {
@@ -270,6 +2179,11 @@ impl VM {
         }
     }
     
+    /// Makes `scope` the current scope, returning the one it replaces.
+    pub fn swap_scope(&mut self, scope: Arc<Mutex<VMScope>>) -> Arc<Mutex<VMScope>> {
+        mem::replace(&mut self.curr_scope, scope)
+    }
+    
     pub fn get_parent_scope(&self) -> Option<Arc<Mutex<VMScope>>> {
         self.curr_scope.lock().unwrap().parent.clone()
     }
@@ -282,132 +2196,549 @@ impl VM {
         self.curr_scope.lock().unwrap().lookup_direct_value(name)
     }
     
-    pub fn lookup_path(&self, path: &Vec<String>) -> Option<&Value> {
-        let mut final_value = None;
-        let mut current_scope = self.get_current_scope();
-        for part in path {
-            if part.is_empty() {
-                let parent_scope = self.get_parent_scope();
-                match parent_scope {
-                    None => return None,
-                    Some(scope) => current_scope = scope,
-                }
-            } else {
-                let final_value = self.lookup_direct_value(&part);
-                match final_value {
-                    None => return None,
-                    Some(value) => match value {
-                        Value::Procedure(procedure) =>
-                            current_scope = procedure.scope.clone(),
-                        _ => return None,
+    /// Finds the value at the end of a path like `.x`, `..x` or `.p.field`. A path starts in the
+    /// current scope. Each empty component goes up to the parent scope, and each name looks up a
+    /// variable of the scope it is in, without searching the parents. Past a variable, names go
+    /// into the scope a procedure was defined in, or get a field of a struct or an attribute of a
+    /// place. A path of only dots names a scope: `.` the current one, and `..` its parent.
+    pub fn lookup_path(&self, path: &[String]) -> Result<Value, ShockError> {
+        enum Cursor {
+            Scope(Arc<Mutex<VMScope>>),
+            Value(Value),
+        }
+        if path.iter().all(String::is_empty) {
+            return Err(ShockError::new(
+                ErrorKind::UnboundName,
+                format!("The path `{}` names a scope, not a value.", ".".repeat(path.len().max(1)))));
+        }
+        // How the path was written up to and including component `i`, for errors.
+        let prefix = |i: usize| path[..=i].iter().map(|part| format!(".{}", part)).collect::<String>();
+        let whole = prefix(path.len() - 1);
+        let mut cursor = Cursor::Scope(self.get_current_scope());
+        for (i, part) in path.iter().enumerate() {
+            cursor = match cursor {
+                Cursor::Value(Value::Procedure(procedure)) => Cursor::Scope(procedure.scope),
+                cursor => cursor,
+            };
+            cursor = match (cursor, part.is_empty()) {
+                (Cursor::Scope(scope), true) => match scope.lock().unwrap().parent.clone() {
+                    None => return Err(ShockError::new(
+                        ErrorKind::UnboundName,
+                        format!("The path `{}` goes past the global scope.", whole))),
+                    Some(parent) => Cursor::Scope(parent),
+                },
+                (Cursor::Scope(scope), false) => match scope.lock().unwrap().lookup_direct_value(part) {
+                    None => return Err(ShockError::new(
+                        ErrorKind::UnboundName,
+                        format!("`{}` in the path `{}` is not defined in that scope.", part, whole))),
+                    Some(value) => Cursor::Value(value),
+                },
+                (Cursor::Value(Value::Struct(fields)), false) => {
+                    match fields.into_iter().find(|(name, _)| name == part) {
+                        None => return Err(ShockError::new(
+                            ErrorKind::OutOfBounds,
+                            format!("The struct at `{}` has no field `{}`.", prefix(i - 1), part))),
+                        Some((_, value)) => Cursor::Value(value),
+                    }
+                },
+                (Cursor::Value(Value::PlaceRef(id)), false) => match self.places.get_place(&id) {
+                    None => return Err(ShockError::new(
+                        ErrorKind::MissingPlace,
+                        format!("The place at `{}` is not in the place store.", prefix(i - 1)))),
+                    Some(place) => match place.get_attr(part) {
+                        None => return Err(ShockError::new(
+                            ErrorKind::OutOfBounds,
+                            format!("The place at `{}` has no attribute `{}`.", prefix(i - 1), part))),
+                        Some(data) => Cursor::Value(nativelib::places::attribute_value(data)),
                     },
-                }
-            }
+                },
+                (Cursor::Value(value), true) => return Err(ShockError::new(
+                    ErrorKind::TypeMismatch,
+                    format!("The path `{}` goes up from {:?} at `{}`, which is not a scope.", whole, value, prefix(i - 1)))),
+                (Cursor::Value(value), false) => return Err(ShockError::new(
+                    ErrorKind::TypeMismatch,
+                    format!("The path `{}` goes into {:?} at `{}`, which has no scope or fields.", whole, value, prefix(i - 1)))),
+            };
+        }
+        match cursor {
+            Cursor::Value(value) => Ok(value),
+            Cursor::Scope(_) => Err(ShockError::new(
+                ErrorKind::UnboundName,
+                format!("The path `{}` names a scope, not a value.", whole))),
         }
-        final_value
+    }
+}
+
+impl Default for VM {
+    fn default() -> Self {
+        VM::new()
     }
 }
 
 pub fn eval(
     vm: &Arc<Mutex<VM>>,
-    expr: &ExpressionValue) -> Value {
+    expr: &ExpressionValue) -> Result<Value, ShockError> {
     eval_impl(vm, expr, true)
 }
 
 pub fn eval_without_dereferencing(
     vm: &Arc<Mutex<VM>>,
-    expr: &ExpressionValue) -> Value {
+    expr: &ExpressionValue) -> Result<Value, ShockError> {
     eval_impl(vm, expr, false)
 }
 
 pub fn eval_impl(
     vm: &Arc<Mutex<VM>>,
     expr: &ExpressionValue,
-    reference_variables: bool) -> Value {
-    println!("EVAL with referencing = {} on {:?}", reference_variables, expr);
-    match expr {
-        ExpressionValue::Path(path_components) => {
-            println!("Path: {:?}", path_components);
-            if reference_variables {
-                match vm.lock().unwrap().lookup_path(path_components) {
-                    None => Value::Unit,
-                    Some(value) => value.clone(),
-                }
-            } else {
-                Value::Path(path_components.clone())
-            }
-        },
-        ExpressionValue::Primitive(primitive_data) => {
-            println!("Primitive: {:?}", primitive_data);
-            match primitive_data {
-                PrimitiveData::Name(name) => if reference_variables {
-                    nativelib::shock_get(vec![(name.to_string(), Value::Unit)], vm)
-                } else {
-                    Value::Primitive(primitive_data.clone())
+    reference_variables: bool) -> Result<Value, ShockError> {
+    eval_code(vm, Code::new(expr.clone()), reference_variables)
+}
+
+/// Evaluates a piece of shared code without copying it.
+pub fn eval_code(
+    vm: &Arc<Mutex<VM>>,
+    code: Code,
+    reference_variables: bool) -> Result<Value, ShockError> {
+    Machine::new(vm).run(Control::Eval(code, reference_variables))
+}
+
+/// Calls a procedure or native with arguments that are already evaluated.
+pub fn call_value(
+    vm: &Arc<Mutex<VM>>,
+    callee: Value,
+    args: Vec<(String, Value)>) -> Result<Value, ShockError> {
+    let mut machine = Machine::new(vm);
+    machine.stack.push(Kont::Call { command: None, restore: None });
+    let control = machine.apply(callee, args).unwrap_or_else(Control::Throw);
+    machine.run(control)
+}
+
+/// A continuation frame: something left to do once the expression being evaluated has a value.
+/// The evaluator keeps these on a stack on the heap rather than recursing, so deep recursion in
+/// Shock does not overflow the Rust stack.
+#[derive(Clone)]
+enum Kont {
+    /// A command being evaluated. Errors that unwind through it get it as a frame, and if it is
+    /// a procedure call, the caller's scope is restored when it returns.
+    Call { command: Option<Code>, restore: Option<Arc<Mutex<VMScope>>> },
+    /// Evaluates the arguments of a command from left to right, then applies the callee.
+    Args { command: Code, callee: Box<Value>, evaluated: Vec<(String, Value)> },
+    List { list: Code, evaluated: Vec<Value> },
+    Struct { fields: Code, evaluated: Vec<(String, Value)> },
+    /// The commands of a block or body that come after the current one.
+    Sequence { block: Code, next: usize, end: usize },
+    /// Binds the parameters of a call in order, evaluating defaults as they come up so that
+    /// they can refer to the parameters before them.
+    Bind { procedure: Box<Procedure>, bindings: Vec<(String, Option<Value>)>, next: usize },
+    /// Restores the scope outside a block once the block is done.
+    Scope(Arc<Mutex<VMScope>>),
+    /// A special form waiting for the value of an expression.
+    Callback(Callback),
+    /// Delimits the continuations that `shift` captures. It restores the scope it was entered
+    /// in, since capturing skips the frames that would otherwise do so.
+    Reset { scope: Arc<Mutex<VMScope>> },
+    /// Calls the handler of a `try` with the condition of an error that unwinds to it. Signals
+    /// find it by searching the stack.
+    Handler { handler: Value, scope: Arc<Mutex<VMScope>> },
+    /// Tells the observer that an expression is done. While there is an observer, every
+    /// expression gets one of these, so tail calls are not eliminated.
+    Exit,
+}
+
+enum Control {
+    /// Evaluate the code, dereferencing names and paths if the flag is set.
+    Eval(Code, bool),
+    /// Pass the value to the frame on top of the stack.
+    Return(Value),
+    /// Unwind the stack with an error.
+    Throw(ShockError),
+}
+
+struct Machine<'a> {
+    vm: &'a Arc<Mutex<VM>>,
+    stack: Vec<Kont>,
+    observer: Option<Observer>,
+}
+
+impl<'a> Machine<'a> {
+    fn new(vm: &'a Arc<Mutex<VM>>) -> Machine<'a> {
+        let observer = vm.lock().unwrap().observer.clone();
+        Machine { vm, stack: vec![], observer }
+    }
+
+    fn observe<F: FnOnce(&mut dyn EvalObserver)>(&self, f: F) {
+        if let Some(observer) = &self.observer {
+            f(&mut *observer.lock().unwrap());
+        }
+    }
+
+    fn run(&mut self, mut control: Control) -> Result<Value, ShockError> {
+        loop {
+            control = match control {
+                Control::Eval(code, dereference) => {
+                    if self.observer.is_some() {
+                        self.observe(|observer| observer.enter_expression(&code));
+                        self.stack.push(Kont::Exit);
+                    }
+                    self.eval(code, dereference).unwrap_or_else(Control::Throw)
                 },
-                _ => Value::Primitive(primitive_data.clone())
-            }
-        },
-        ExpressionValue::Procedure(args, commands) => {
-            println!("Procedure...");
-            Value::Procedure(Procedure{
-                argnames: args.iter().map(|val| val.0.clone()).collect(),
-                body: commands.clone(),
-                scope: vm.lock().unwrap().curr_scope.clone(),
-            })
-        },
-        ExpressionValue::Unit => {
-            Value::Unit
-        },
-        ExpressionValue::Expression(command) => {
-            let looked_up_value = vm.lock().unwrap().curr_scope.lock().unwrap().lookup_value(&command.name);
-            match looked_up_value {
-                None => {
-                    println!("Could not find procedure.");
-                    Value::Unit
+                Control::Return(value) => match self.stack.pop() {
+                    None => return Ok(value),
+                    Some(kont) => self.resume(kont, value).unwrap_or_else(Control::Throw),
                 },
-                Some(val) => {
-                    match val {
-                        Value::NativeProcedure(mut natproc) => {
-                            natproc.apply(command.args.iter().map(
-                                |x| (x.0.clone(), eval_without_dereferencing(vm, &x.1))
-                            ).collect(), vm)
-                        },
-                        Value::Procedure(mut proc) => {
-                            vm.lock().unwrap().push_scope();
-                            let value = proc.apply(command.args.iter().map(
-                                |x| (x.0.clone(), eval(vm, &x.1))
-                            ).collect(), vm);
-                            vm.lock().unwrap().pop_scope();
-                            value
-                        },
-                        _ => {
-                            println!("Cannot apply a non-procedure in a command.");
-                            Value::Unit
-                        }
-                    }
+                Control::Throw(err) => match self.stack.pop() {
+                    None => return Err(err),
+                    Some(kont) => self.unwind(kont, err),
                 },
             }
-        },
-        ExpressionValue::Block(commands) => {
-            println!("Block... ");
-            vm.lock().unwrap().push_scope();
-            let mut final_value = Value::Unit;
-            for command in commands {
-                final_value = eval(&vm, &ExpressionValue::Expression(command.clone()));
+        }
+    }
+
+    fn eval(&mut self, code: Code, dereference: bool) -> Result<Control, ShockError> {
+        let expr = match code.node() {
+            Node::Command(_) => return self.eval_command(code),
+            Node::Expression(expr) => expr,
+        };
+        Ok(match expr {
+            ExpressionValue::Expression(_) => return self.eval_command(code.clone()),
+            ExpressionValue::Path(path_components, _) => if dereference {
+                Control::Return(self.vm.lock().unwrap().lookup_path(path_components)?)
+            } else {
+                Control::Return(Value::Path(path_components.clone()))
+            },
+            ExpressionValue::Primitive(PrimitiveData::Name(name), _) if dereference =>
+                Control::Return(nativelib::lookup(name, self.vm)?),
+            ExpressionValue::Primitive(primitive_data, _) =>
+                Control::Return(Value::Primitive(primitive_data.clone())),
+            ExpressionValue::Procedure(..) => {
+                let scope = self.vm.lock().unwrap().get_current_scope();
+                Control::Return(Value::Procedure(Procedure::new(code.clone(), scope)?))
+            },
+            ExpressionValue::List(elements, _) => if elements.is_empty() {
+                Control::Return(Value::List(vec![]))
+            } else {
+                self.stack.push(Kont::List { list: code.clone(), evaluated: vec![] });
+                Control::Eval(code.child(0), true)
+            },
+            ExpressionValue::Struct(fields, _) => if fields.is_empty() {
+                Control::Return(Value::Struct(vec![]))
+            } else {
+                self.stack.push(Kont::Struct { fields: code.clone(), evaluated: vec![] });
+                Control::Eval(code.child(0), true)
+            },
+            ExpressionValue::Unit => Control::Return(Value::Unit),
+            ExpressionValue::Block(commands, _) => {
+                let mut vm = self.vm.lock().unwrap();
+                let block_scope = VMScope::new(Some(vm.get_current_scope()));
+                let outer_scope = vm.swap_scope(Arc::new(Mutex::new(block_scope)));
+                drop(vm);
+                self.observe(|observer| observer.push_scope());
+                self.stack.push(Kont::Scope(outer_scope));
+                self.sequence(code.clone(), 0, commands.len())
+            },
+        })
+    }
+
+    /// Evaluates the children of `block` from `next` to `end` in order, with the value of the
+    /// last one as the result. The last one is evaluated without a frame of its own.
+    fn sequence(&mut self, block: Code, next: usize, end: usize) -> Control {
+        if next == end {
+            return Control::Return(Value::Unit);
+        }
+        let command = block.child(next);
+        if next + 1 < end {
+            self.stack.push(Kont::Sequence { block, next: next + 1, end });
+        }
+        Control::Eval(command, true)
+    }
+
+    fn eval_command(&mut self, code: Code) -> Result<Control, ShockError> {
+        self.stack.push(Kont::Call { command: Some(code.clone()), restore: None });
+        let command = code.command();
+        let callee = self.vm.lock().unwrap().curr_scope.lock().unwrap().lookup_value(&command.name);
+        match callee {
+            None => Err(ShockError::new(
+                ErrorKind::UnboundName,
+                format!("Could not find procedure `{}`.", command.name))),
+            Some(Value::SpecialForm(form)) => {
+                let args = command.args.iter().enumerate()
+                    .map(|(i, (label, _))| (label.clone(), code.child(i)))
+                    .collect();
+                let step = form.apply(args, self.vm)?;
+                self.step(step)
+            },
+            Some(callee @ Value::NativeProcedure(_)) |
+            Some(callee @ Value::Procedure(_)) |
+            Some(callee @ Value::Continuation(_)) => {
+                let evaluated = Vec::with_capacity(command.args.len());
+                self.arguments(code.clone(), Box::new(callee), evaluated)
+            },
+            Some(_) => Err(ShockError::new(
+                ErrorKind::NotApplicable,
+                format!("`{}` is not a procedure.", command.name))),
+        }
+    }
+
+    /// Evaluates the next argument of `command`, or applies the callee once there are none left.
+    fn arguments(&mut self, command: Code, callee: Box<Value>, evaluated: Vec<(String, Value)>) -> Result<Control, ShockError> {
+        let next = evaluated.len();
+        if next == command.command().args.len() {
+            return self.apply(*callee, evaluated);
+        }
+        // Natives get names and paths as they are, so that e.g. `let` can tell what to bind.
+        let dereference = !matches!(*callee, Value::NativeProcedure(_));
+        let arg = command.child(next);
+        self.stack.push(Kont::Args { command, callee, evaluated });
+        Ok(Control::Eval(arg, dereference))
+    }
+
+    /// Applies a callee to its arguments. The frame of the call is on top of the stack.
+    fn apply(&mut self, callee: Value, args: Vec<(String, Value)>) -> Result<Control, ShockError> {
+        match callee {
+            Value::NativeProcedure(mut native) => {
+                if self.observer.is_none() {
+                    return Ok(Control::Return(native.apply(args, self.vm)?));
+                }
+                let name = match self.stack.last() {
+                    Some(Kont::Call { command: Some(command), .. }) => command.command().name.clone(),
+                    _ => "native".to_owned(),
+                };
+                let observed = args.clone();
+                let result = native.apply(args, self.vm);
+                self.observe(|observer| observer.native_call(&name, &observed, result.as_ref()));
+                Ok(Control::Return(result?))
+            },
+            Value::Procedure(procedure) => {
+                let bindings = procedure.bind_arguments(args)?;
+                // The call runs in a fresh scope under the scope the procedure was defined in,
+                // not under the caller's scope.
+                let call_scope = Arc::new(Mutex::new(VMScope::new(Some(procedure.scope.clone()))));
+                let caller_scope = self.vm.lock().unwrap().swap_scope(call_scope);
+                self.observe(|observer| observer.push_scope());
+                self.enter_call(caller_scope);
+                self.bind(Box::new(procedure), bindings, 0)
+            },
+            Value::Continuation(continuation) => {
+                let value = match args.len() {
+                    0 => Value::Unit,
+                    1 => args.into_iter().next().unwrap().1,
+                    n => return Err(ShockError::new(
+                        ErrorKind::Arity,
+                        format!("A continuation takes one value, but got {}.", n))),
+                };
+                // Resuming is delimited too, so that the value of the resumed computation is
+                // returned here.
+                let scope = self.vm.lock().unwrap().swap_scope(continuation.scope.clone());
+                self.stack.push(Kont::Reset { scope });
+                self.stack.extend(continuation.frames.iter().cloned());
+                Ok(Control::Return(value))
+            },
+            other => Err(ShockError::new(
+                ErrorKind::NotApplicable,
+                format!("{:?} is not a procedure.", other))),
+        }
+    }
+
+    /// Makes the frame on top of the stack restore `caller_scope` once the call returns.
+    fn enter_call(&mut self, caller_scope: Arc<Mutex<VMScope>>) {
+        let len = self.stack.len();
+        let is_tail_call = matches!(self.stack.get(len.wrapping_sub(2)), Some(Kont::Call { restore: Some(_), .. }));
+        // Right below is the frame of a procedure call whose body has nothing left to do but
+        // return the value of this call. Its frame already restores the scope the caller was
+        // called from, so the caller's own scope is dropped and the two frames are merged into
+        // one, which shows the latest call in error traces. This keeps tail recursion from
+        // growing the stack.
+        if is_tail_call {
+            self.observe(|observer| observer.pop_scope());
+            if let Some(Kont::Call { command: Some(command), .. }) = self.stack.pop() {
+                if let Some(Kont::Call { command: caller, .. }) = self.stack.last_mut() {
+                    *caller = Some(command);
+                }
+            }
+            return;
+        }
+        if let Some(Kont::Call { restore, .. }) = self.stack.last_mut() {
+            *restore = Some(caller_scope);
+        }
+    }
+
+    fn bind(&mut self, procedure: Box<Procedure>, mut bindings: Vec<(String, Option<Value>)>, next: usize) -> Result<Control, ShockError> {
+        for i in next..bindings.len() {
+            match bindings[i].1.take() {
+                Some(value) => self.vm.lock().unwrap().curr_scope.lock().unwrap().set_value(&bindings[i].0, &value),
+                None => {
+                    let default = procedure.params[i].default.clone().unwrap();
+                    self.stack.push(Kont::Bind { procedure, bindings, next: i });
+                    return Ok(Control::Eval(default, true));
+                },
             }
-            vm.lock().unwrap().pop_scope();
-            return final_value;
+        }
+        let (start, end) = procedure.body_range();
+        Ok(self.sequence(procedure.code, start, end))
+    }
+
+    /// Carries out what a special form asked for. The form's frame is on top of the stack.
+    fn step(&mut self, step: Step) -> Result<Control, ShockError> {
+        Ok(match step {
+            Step::Value(value) => Control::Return(value),
+            Step::Eval(code) => {
+                // Drop the form's frame, so that the expression takes the form's place.
+                self.stack.pop();
+                Control::Eval(code, true)
+            },
+            Step::Then(code, callback) => {
+                self.stack.push(Kont::Callback(callback));
+                Control::Eval(code, true)
+            },
+            Step::Apply(callee, args) => return self.apply(callee, args),
+            Step::Reset(code) => {
+                let scope = self.vm.lock().unwrap().get_current_scope();
+                self.stack.push(Kont::Reset { scope });
+                Control::Eval(code, true)
+            },
+            Step::Shift(procedure) => {
+                // Without a `reset`, the continuation is the rest of the evaluation.
+                let prompt = self.stack.iter().rposition(|kont| matches!(kont, Kont::Reset { .. }));
+                let frames = self.stack.split_off(prompt.map_or(0, |i| i + 1));
+                let scope = self.vm.lock().unwrap().get_current_scope();
+                let continuation = Continuation { frames: Arc::new(frames), scope };
+                self.stack.push(Kont::Call { command: None, restore: None });
+                return self.apply(procedure, vec![(String::new(), Value::Continuation(continuation))]);
+            },
+            Step::Try(code, handler) => {
+                let scope = self.vm.lock().unwrap().get_current_scope();
+                self.stack.push(Kont::Handler { handler, scope });
+                Control::Eval(code, true)
+            },
+            Step::Signal(condition) => {
+                let found = self.stack.iter().rposition(|kont| matches!(kont, Kont::Handler { .. }));
+                let frames = match found {
+                    None => return Err(ShockError::raised(condition)),
+                    Some(i) => self.stack.split_off(i),
+                };
+                let (handler, handler_scope) = match &frames[0] {
+                    Kont::Handler { handler, scope } => (handler.clone(), scope.clone()),
+                    _ => unreachable!(),
+                };
+                // The handler runs where its `try` was, and keeps handling the signals of the
+                // computation it resumes.
+                let scope = self.vm.lock().unwrap().swap_scope(handler_scope);
+                let continuation = Continuation { frames: Arc::new(frames), scope };
+                self.stack.push(Kont::Call { command: None, restore: None });
+                let args = vec![(String::new(), condition), (String::new(), Value::Continuation(continuation))];
+                return self.apply(handler, args);
+            },
+        })
+    }
+
+    fn resume(&mut self, kont: Kont, value: Value) -> Result<Control, ShockError> {
+        Ok(match kont {
+            Kont::Call { restore, .. } => {
+                if let Some(scope) = restore {
+                    self.vm.lock().unwrap().swap_scope(scope);
+                    self.observe(|observer| observer.pop_scope());
+                }
+                Control::Return(value)
+            },
+            Kont::Args { command, callee, mut evaluated } => {
+                let label = command.command().args[evaluated.len()].0.clone();
+                evaluated.push((label, value));
+                return self.arguments(command, callee, evaluated);
+            },
+            Kont::List { list, mut evaluated } => {
+                evaluated.push(value);
+                match list.expr() {
+                    ExpressionValue::List(elements, _) if evaluated.len() < elements.len() => {
+                        let next = list.child(evaluated.len());
+                        self.stack.push(Kont::List { list, evaluated });
+                        Control::Eval(next, true)
+                    },
+                    _ => Control::Return(Value::List(evaluated)),
+                }
+            },
+            Kont::Struct { fields, mut evaluated } => {
+                let (label, len) = match fields.expr() {
+                    ExpressionValue::Struct(labeled, _) => (labeled[evaluated.len()].0.clone(), labeled.len()),
+                    _ => unreachable!(),
+                };
+                evaluated.push((label, value));
+                if evaluated.len() < len {
+                    let next = fields.child(evaluated.len());
+                    self.stack.push(Kont::Struct { fields, evaluated });
+                    Control::Eval(next, true)
+                } else {
+                    Control::Return(Value::Struct(evaluated))
+                }
+            },
+            Kont::Sequence { block, next, end } => self.sequence(block, next, end),
+            Kont::Bind { procedure, mut bindings, next } => {
+                bindings[next].1 = Some(value);
+                return self.bind(procedure, bindings, next);
+            },
+            Kont::Scope(scope) => {
+                self.vm.lock().unwrap().swap_scope(scope);
+                self.observe(|observer| observer.pop_scope());
+                Control::Return(value)
+            },
+            Kont::Reset { scope } | Kont::Handler { scope, .. } => {
+                self.vm.lock().unwrap().swap_scope(scope);
+                Control::Return(value)
+            },
+            Kont::Exit => {
+                self.observe(|observer| observer.exit_expression(Ok(&value)));
+                Control::Return(value)
+            },
+            Kont::Callback(callback) => {
+                let step = callback(value, self.vm)?;
+                return self.step(step);
+            },
+        })
+    }
+
+    /// Undoes what a frame set up, so that the VM stays usable after an error, or hands the
+    /// error to a handler.
+    fn unwind(&mut self, kont: Kont, err: ShockError) -> Control {
+        match kont {
+            Kont::Call { command, restore } => {
+                if let Some(scope) = restore {
+                    self.vm.lock().unwrap().swap_scope(scope);
+                    self.observe(|observer| observer.pop_scope());
+                }
+                match command {
+                    Some(command) => Control::Throw(err.in_command(command.command())),
+                    None => Control::Throw(err),
+                }
+            },
+            Kont::Scope(scope) => {
+                self.vm.lock().unwrap().swap_scope(scope);
+                self.observe(|observer| observer.pop_scope());
+                Control::Throw(err)
+            },
+            Kont::Reset { scope } => {
+                self.vm.lock().unwrap().swap_scope(scope);
+                Control::Throw(err)
+            },
+            Kont::Exit => {
+                self.observe(|observer| observer.exit_expression(Err(&err)));
+                Control::Throw(err)
+            },
+            Kont::Handler { handler, scope } => {
+                self.vm.lock().unwrap().swap_scope(scope);
+                self.stack.push(Kont::Call { command: None, restore: None });
+                self.apply(handler, vec![(String::new(), err.condition())]).unwrap_or_else(Control::Throw)
+            },
+            _ => Control::Throw(err),
         }
     }
 }
 
+
 fn extract_raw_name(args: &Vec<(String, ExpressionValue)>, pos: usize) -> String {
     let mut var_name = args.get(pos).unwrap().0.clone();
     if var_name == "" {
         let var_name_data = args.get(pos).unwrap().1.clone();
         match var_name_data {
-            ExpressionValue::Primitive(data) => match data {
+            ExpressionValue::Primitive(data, _) => match data {
                 PrimitiveData::Name(s) => {
                     var_name = s;
                 },
@@ -436,3 +2767,606 @@ fn extract_first_argname(args: &Vec<(String, Value)>, pos: usize) -> String {
     var_name
 }
 
+
+#[cfg(test)]
+mod tests {
+    use crate::interpreter::{VM, VMScope, Value, ShockError, ErrorKind, eval};
+    use crate::parser::{parse, ExpressionValue};
+    use crate::model::PrimitiveData;
+    use std::sync::{Arc, Mutex};
+    use std::sync::atomic::{AtomicUsize, Ordering as AtomicOrdering};
+    
+    fn new_vm() -> Arc<Mutex<VM>> {
+        let vm = Arc::new(Mutex::new(VM::new()));
+        vm.lock().unwrap().define_standard_functions();
+        vm
+    }
+    
+    fn try_run(vm: &Arc<Mutex<VM>>, source: &str) -> Result<Value, ShockError> {
+        let mut value = Value::Unit;
+        for expression in parse(source).unwrap().iter() {
+            value = eval(vm, expression)?;
+        }
+        Ok(value)
+    }
+    
+    /// Evaluates every expression in the source, returning the value of the last one.
+    fn run(vm: &Arc<Mutex<VM>>, source: &str) -> Value {
+        match try_run(vm, source) {
+            Ok(value) => value,
+            Err(err) => panic!("{}", err),
+        }
+    }
+    
+    /// Evaluates the source, which is expected to fail, and returns the kind of error.
+    fn fail(vm: &Arc<Mutex<VM>>, source: &str) -> ErrorKind {
+        match try_run(vm, source) {
+            Ok(value) => panic!("Expected an error, but got {:?}.", value),
+            Err(err) => err.kind,
+        }
+    }
+    
+    fn int(i: i64) -> Value {
+        Value::Primitive(PrimitiveData::Int(i))
+    }
+    
+    fn boolean(b: bool) -> Value {
+        Value::Primitive(PrimitiveData::Bool(b))
+    }
+    
+    #[test]
+    fn list_natives() {
+        // Given: a list
+        let vm = new_vm();
+        run(&vm, "let xs = [1, 2, (+ 1 2)]");
+        
+        // Then: we can get its length and index into it
+        assert_eq!(int(3), run(&vm, "length xs"));
+        assert_eq!(int(2), run(&vm, "index xs 1"));
+        assert_eq!(ErrorKind::OutOfBounds, fail(&vm, "index xs 3"));
+        
+        // When: we append to the list
+        // Then: we get a new, longer list
+        assert_eq!(
+            Value::List(vec![int(1), int(2), int(3), int(4), int(3)]),
+            run(&vm, "append xs 4 (length xs)"));
+        assert_eq!(int(3), run(&vm, "length xs"));
+    }
+    
+    #[test]
+    fn struct_natives() {
+        // Given: a struct
+        let vm = new_vm();
+        run(&vm, "let p = (x: 1, y: [2, 3])");
+        
+        // Then: we can get its fields by name
+        assert_eq!(int(1), run(&vm, "field p x"));
+        assert_eq!(
+            Value::List(vec![int(2), int(3)]),
+            run(&vm, "field p y"));
+        assert_eq!(int(2), run(&vm, "length p"));
+        assert_eq!(ErrorKind::OutOfBounds, fail(&vm, "field p z"));
+    }
+    
+    #[test]
+    fn paths() {
+        let vm = new_vm();
+        run(&vm, "let x = 1");
+        run(&vm, "let p = (a: 1, b: (c: 2))");
+        
+        // Given: a block that shadows `x`
+        // Then: `.x` is the block's `x`, and `..x` is the one in the scope around it
+        assert_eq!(
+            Value::List(vec![int(2), int(1), int(2)]),
+            run(&vm, "let r = { let x = 2; let z = [.x, ..x, x] }"));
+        assert_eq!(ErrorKind::UnboundName, fail(&vm, "let r = { let y = 2; let z = .x }"));
+        assert_eq!(ErrorKind::UnboundName, fail(&vm, "..x"));
+        
+        // Given: a procedure that closes over a variable
+        // Then: the path goes into the scope it was defined in
+        run(&vm, "let make = [n: Int]{ let count = n; let get = []{ count } }");
+        run(&vm, "let get = (make 5)");
+        assert_eq!(int(5), run(&vm, ".get.count"));
+        assert_eq!(int(6), run(&vm, "+ .get.count 1"));
+        
+        // Given: nested structs
+        // Then: the path gets their fields
+        assert_eq!(int(2), run(&vm, ".p.b.c"));
+        assert_eq!(ErrorKind::OutOfBounds, fail(&vm, ".p.b.d"));
+        assert_eq!(ErrorKind::TypeMismatch, fail(&vm, ".x.y"));
+        assert_eq!(ErrorKind::TypeMismatch, fail(&vm, ".p."));
+        
+        // Then: errors say which path failed
+        let err = try_run(&vm, ".get.nope").unwrap_err();
+        assert_eq!("`nope` in the path `.get.nope` is not defined in that scope.", err.message);
+    }
+    
+    #[test]
+    fn places() {
+        // Given: two places, one linking to the other
+        let vm = new_vm();
+        run(&vm, "let root = (place name: \"root\")");
+        run(&vm, "let child = (place size: 3)");
+        run(&vm, "link root kid child");
+        let child = run(&vm, ".child");
+        
+        // Then: their attributes and links can be read by name, by following links or by path
+        assert_eq!(Value::Primitive(PrimitiveData::String("root".to_owned())), run(&vm, "get_attr root name"));
+        assert_eq!(child, run(&vm, "follow root kid"));
+        assert_eq!(child, run(&vm, "get_attr root kid"));
+        assert_eq!(int(3), run(&vm, ".root.kid.size"));
+        
+        // When: an attribute is changed through one reference
+        // Then: every reference sees it, since they are the same place
+        run(&vm, "put_attr child size 4");
+        assert_eq!(int(4), run(&vm, "get_attr (follow root kid) size"));
+        
+        // When: the link is removed
+        // Then: it cannot be followed anymore
+        assert_eq!(child, run(&vm, "remove_attr root kid"));
+        assert_eq!(ErrorKind::OutOfBounds, fail(&vm, "follow root kid"));
+        
+        // Then: only primitive data goes in attributes, and only links can be followed
+        assert_eq!(ErrorKind::TypeMismatch, fail(&vm, "put_attr root kid child"));
+        assert_eq!(ErrorKind::TypeMismatch, fail(&vm, "put_attr root xs [1, 2]"));
+        assert_eq!(ErrorKind::TypeMismatch, fail(&vm, "follow root name"));
+        assert_eq!(ErrorKind::TypeMismatch, fail(&vm, "get_attr 1 name"));
+        
+        // When: the child is possessed again
+        // Then: it can only have one owner, but can be referred to by any place
+        run(&vm, "link root kid child");
+        run(&vm, "let other = (place)");
+        assert_eq!(ErrorKind::InvalidEdit, fail(&vm, "link other kid child"));
+        assert_eq!(ErrorKind::InvalidEdit, fail(&vm, "link child parent root"));
+        assert_eq!(child, run(&vm, "refer other kid child"));
+        assert_eq!(child, run(&vm, "follow other kid"));
+    }
+    
+    #[test]
+    fn integer_arithmetic_is_exact() {
+        // Numbers of different kinds compare equal, so compare the Debug output to also check
+        // the kind of the result.
+        let vm = new_vm();
+        let show = |source: &str| format!("{:?}", run(&vm, source));
+        
+        // Given: Int operands
+        // Then: the result stays an Int, even when it is zero or too large for an f64
+        assert_eq!(format!("{:?}", int(0)), show("- 2 2"));
+        assert_eq!(format!("{:?}", int(9007199254740993)), show("+ 9007199254740992 1"));
+        assert_eq!(format!("{:?}", int(-5)), show("- 5"));
+        assert_eq!(format!("{:?}", int(6)), show("* 1 2 3"));
+        
+        // Given: integer division and remainder
+        // Then: division rounds down and the remainder takes the sign of the divisor
+        assert_eq!(format!("{:?}", int(3)), show("// 7 2"));
+        assert_eq!(format!("{:?}", int(-4)), show("// -7 2"));
+        assert_eq!(format!("{:?}", int(1)), show("% 7 2"));
+        assert_eq!(format!("{:?}", int(1)), show("% -7 2"));
+        assert_eq!(format!("{:?}", int(-1)), show("% 7 -2"));
+        assert_eq!(format!("{:?}", Value::Primitive(PrimitiveData::Float(3.5))), show("/ 7 2"));
+        
+        // Given: overflow, division by zero or a non-number
+        // Then: evaluation fails
+        assert_eq!(ErrorKind::Arithmetic, fail(&vm, "+ 9223372036854775807 1"));
+        assert_eq!(ErrorKind::Arithmetic, fail(&vm, "// 1 0"));
+        assert_eq!(ErrorKind::Arithmetic, fail(&vm, "% 1 0"));
+        assert_eq!(ErrorKind::TypeMismatch, fail(&vm, "+ 1 true"));
+    }
+    
+    #[test]
+    fn numeric_tower() {
+        let vm = new_vm();
+        let show = |source: &str| format!("{:?}", run(&vm, source));
+        
+        // Given: Unsigned and Byte operands
+        // Then: they keep their own kind and range
+        assert_eq!(format!("{:?}", Value::Primitive(PrimitiveData::Unsigned(5))), show("+ 2u 3u"));
+        assert_eq!(ErrorKind::Arithmetic, fail(&vm, "- 2u 3u"));
+        assert_eq!(format!("{:?}", Value::Primitive(PrimitiveData::Byte(255))), show("+ 250u8 5u8"));
+        assert_eq!(ErrorKind::Arithmetic, fail(&vm, "+ 250u8 6u8"));
+        assert_eq!(ErrorKind::Arithmetic, fail(&vm, "- 1u8"));
+        assert_eq!(format!("{:?}", Value::Primitive(PrimitiveData::Unsigned(0))), show("- 0u"));
+        
+        // Given: mixed operands
+        // Then: the narrower one is widened, and a Float makes the result a Float
+        assert_eq!(format!("{:?}", Value::Primitive(PrimitiveData::Unsigned(300))), show("+ 250u8 50u"));
+        assert_eq!(format!("{:?}", int(5)), show("- 2u -3 0"));
+        assert_eq!(format!("{:?}", Value::Primitive(PrimitiveData::Float(3.5))), show("+ 1 2.5"));
+        assert_eq!(format!("{:?}", Value::Primitive(PrimitiveData::Float(2.0))), show("* 4 0.5"));
+        assert_eq!(ErrorKind::Arithmetic, fail(&vm, "+ 18446744073709551615u -1"));
+    }
+    
+    #[test]
+    fn conditional_special_forms() {
+        let vm = new_vm();
+        
+        // Given: an if with labeled or positional branches
+        // Then: only the branch that is taken gets evaluated
+        assert_eq!(int(1), run(&vm, "if true then: 1 else: (let x = 5)"));
+        assert_eq!(ErrorKind::UnboundName, fail(&vm, "[x]"));
+        assert_eq!(int(2), run(&vm, "if cond: false 1 2"));
+        assert_eq!(Value::Unit, run(&vm, "if false 1"));
+        
+        // Given: when and unless
+        // Then: the body is only evaluated for a true or false condition respectively
+        assert_eq!(int(3), run(&vm, "when 0 3"));
+        assert_eq!(Value::Unit, run(&vm, "unless 0 3"));
+        assert_eq!(int(4), run(&vm, "unless false then: 4"));
+        
+        // Given: cond with several clauses
+        // Then: the first matching clause wins, and else matches anything
+        assert_eq!(int(2), run(&vm, "cond [false, 1] [true, 2] [else, 3]"));
+        assert_eq!(int(3), run(&vm, "cond [false, 1] [else, 3]"));
+        assert_eq!(Value::Unit, run(&vm, "cond [false, 1]"));
+    }
+    
+    #[test]
+    fn boolean_special_forms_short_circuit() {
+        let vm = new_vm();
+        
+        // Given: and/or whose result is known before the last argument
+        // Then: the remaining arguments are not evaluated
+        let falsy = Value::Primitive(PrimitiveData::Bool(false));
+        let truthy = Value::Primitive(PrimitiveData::Bool(true));
+        assert_eq!(falsy, run(&vm, "and true false (let y = 1)"));
+        assert_eq!(truthy, run(&vm, "or false 0 (let y = 1)"));
+        assert_eq!(ErrorKind::UnboundName, fail(&vm, "[y]"));
+        assert_eq!(truthy, run(&vm, "and"));
+    }
+    
+    #[test]
+    fn value_equality_and_ordering() {
+        // Given: numbers of different kinds
+        // Then: they are compared by value
+        assert_eq!(int(1), Value::Primitive(PrimitiveData::Float(1.0)));
+        assert_eq!(Value::Primitive(PrimitiveData::Unsigned(u64::MAX)) > int(i64::MAX), true);
+        assert_eq!(Value::Primitive(PrimitiveData::Byte(3)) < Value::Primitive(PrimitiveData::Float(3.5)), true);
+        
+        // Given: values of different kinds
+        // Then: they are not equal and have no order
+        assert_ne!(int(1), boolean(true));
+        assert_ne!(Value::Primitive(PrimitiveData::String("a".to_owned())), Value::Primitive(PrimitiveData::Name("a".to_owned())));
+        assert_eq!(None, int(1).partial_cmp(&Value::Unit));
+        
+        // Given: lists
+        // Then: they are compared element by element
+        assert_eq!(Value::List(vec![int(1), int(2)]), Value::List(vec![int(1), Value::Primitive(PrimitiveData::Float(2.0))]));
+        assert_eq!(Value::List(vec![int(1), int(2)]) < Value::List(vec![int(1), int(3)]), true);
+        assert_eq!(Value::List(vec![int(1)]) < Value::List(vec![int(1), int(0)]), true);
+    }
+    
+    #[test]
+    fn comparison_natives() {
+        let vm = new_vm();
+        run(&vm, "let x = 2");
+        
+        // Given: comparisons of variables and literals
+        // Then: they hold pairwise along all arguments
+        assert_eq!(boolean(true), run(&vm, "== x 2 2.0"));
+        assert_eq!(boolean(false), run(&vm, "!= x 2u"));
+        assert_eq!(boolean(true), run(&vm, "< 1 x 3"));
+        assert_eq!(boolean(false), run(&vm, "< 1 x x"));
+        assert_eq!(boolean(true), run(&vm, "<= 1 x x"));
+        assert_eq!(boolean(true), run(&vm, "> \"b\" \"a\""));
+        assert_eq!(boolean(true), run(&vm, ">= true false"));
+        assert_eq!(boolean(false), run(&vm, "== [1, 2] [1]"));
+        assert_eq!(boolean(false), run(&vm, "== 1 \"1\""));
+        
+        // Given: an ordering between values of different kinds
+        // Then: evaluation fails
+        assert_eq!(ErrorKind::TypeMismatch, fail(&vm, "< 1 \"1\""));
+        assert_eq!(ErrorKind::Arity, fail(&vm, "== 1"));
+        
+        // Given: not
+        // Then: it negates the truthiness of its argument
+        assert_eq!(boolean(true), run(&vm, "not false"));
+        assert_eq!(boolean(false), run(&vm, "not 0"));
+        assert_eq!(boolean(true), run(&vm, "if (not (< x 1)) then: true else: false"));
+    }
+    
+    #[test]
+    fn procedure_parameter_binding() {
+        // Given: a procedure with typed parameters and a return type
+        let vm = new_vm();
+        run(&vm, "let sub = [x: Int y: Int -> Int]{ - x y }");
+        
+        // Then: arguments are bound by position or by name
+        assert_eq!(int(2), run(&vm, "sub 5 3"));
+        assert_eq!(int(2), run(&vm, "sub y: 3 x: 5"));
+        assert_eq!(int(2), run(&vm, "sub y: 3 5"));
+        
+        // Then: the return type is not a parameter
+        assert_eq!(ErrorKind::Arity, fail(&vm, "sub 5 3 1"));
+        
+        // Then: missing, unknown or repeated arguments are rejected
+        assert_eq!(ErrorKind::Arity, fail(&vm, "sub 5"));
+        assert_eq!(ErrorKind::Arity, fail(&vm, "sub 5 z: 3"));
+        assert_eq!(ErrorKind::Arity, fail(&vm, "sub x: 5 x: 3"));
+    }
+    
+    #[test]
+    fn procedure_default_values() {
+        // Given: a procedure whose later parameters have default values
+        let vm = new_vm();
+        run(&vm, "let f = [x: Int y: Int = 10 z: Int = (* y 2)]{ + x y z }");
+        
+        // Then: defaults fill in missing arguments, and can refer to earlier parameters
+        assert_eq!(int(31), run(&vm, "f 1"));
+        assert_eq!(int(7), run(&vm, "f 1 2"));
+        assert_eq!(int(6), run(&vm, "f 1 2 3"));
+        assert_eq!(int(13), run(&vm, "f 1 z: 2"));
+        assert_eq!(ErrorKind::Arity, fail(&vm, "f z: 2"));
+    }
+    
+    #[test]
+    fn procedure_declarations() {
+        use crate::interpreter::{Procedure, Code};
+        use crate::parser::{ArgumentList, Span};
+        
+        let scope = Arc::new(Mutex::new(VMScope::new(None)));
+        let name = |n: &str| ExpressionValue::Primitive(PrimitiveData::Name(n.to_owned()), Span::default());
+        let declare = |args: ArgumentList| Procedure::new(
+            Code::new(ExpressionValue::Procedure(args, vec![], Span::default())),
+            scope.clone());
+        
+        // Given: an argument list with a return type
+        // Then: it is kept apart from the parameters
+        let procedure = declare(vec![("x".to_owned(), name("Int")), ("->".to_owned(), name("Int"))]).unwrap();
+        assert_eq!(1, procedure.params.len());
+        assert_eq!(Some(name("Int")), procedure.return_type);
+        
+        // Given: a default value without a parameter, or a duplicate parameter
+        // Then: the procedure cannot be declared
+        assert!(declare(vec![("=".to_owned(), name("a"))]).is_err());
+        assert!(declare(vec![("x".to_owned(), name("Int")), ("x".to_owned(), name("Int"))]).is_err());
+    }
+    
+    #[test]
+    fn closures_keep_their_own_counters() {
+        // Given: a procedure that returns a closure over a local variable
+        let vm = new_vm();
+        run(&vm, "let make_counter = [start: Int]{ let count = start; let next = []{ set count = (+ count 1) } }");
+        run(&vm, "let a = (make_counter 0)");
+        run(&vm, "let b = (make_counter 10)");
+        
+        // When: the closures are called
+        // Then: each one updates the variable it captured, and only that one
+        assert_eq!(int(1), run(&vm, "a"));
+        assert_eq!(int(2), run(&vm, "a"));
+        assert_eq!(int(11), run(&vm, "b"));
+        assert_eq!(int(3), run(&vm, "a"));
+        assert_eq!(ErrorKind::UnboundName, fail(&vm, "[count]"));
+    }
+    
+    #[test]
+    fn closures_support_currying() {
+        // Given: a curried procedure
+        let vm = new_vm();
+        run(&vm, "let adder = [x: Int]{ let add = [y: Int]{ + x y } }");
+        run(&vm, "let add2 = (adder 2)");
+        run(&vm, "let add5 = (adder 5)");
+        
+        // Then: each partial application remembers its own argument
+        assert_eq!(int(5), run(&vm, "add2 3"));
+        assert_eq!(int(8), run(&vm, "add5 3"));
+        assert_eq!(int(9), run(&vm, "+ (add2 1) (add5 1)"));
+    }
+    
+    #[test]
+    fn procedures_are_lexically_scoped() {
+        // Given: a procedure that reads a variable from where it was defined
+        let vm = new_vm();
+        run(&vm, "let x = 1");
+        run(&vm, "let read_x = []{ get x }");
+        
+        // When: it is called from a scope that shadows that variable
+        // Then: it still sees the variable from its definition
+        run(&vm, "let shadow = [x: Int]{ read_x }");
+        assert_eq!(int(1), run(&vm, "shadow 2"));
+        
+        // Given: a parameter that shadows a global
+        // Then: the parameter wins inside the procedure, and the global is untouched
+        run(&vm, "let inner = [x: Int]{ + x 10 }");
+        assert_eq!(int(12), run(&vm, "inner 2"));
+        assert_eq!(int(1), run(&vm, "get x"));
+        
+        // Given: a local let inside a procedure
+        // Then: it does not leak into the global scope
+        run(&vm, "let local = []{ let x = 100 }");
+        assert_eq!(int(100), run(&vm, "local"));
+        assert_eq!(int(1), run(&vm, "get x"));
+    }
+    
+    #[test]
+    fn errors_carry_the_shock_call_stack() {
+        use crate::parser::FileId;
+        
+        // Given: a procedure whose body fails
+        let vm = new_vm();
+        let source = "let half = [x: Int]{ // x 0 }\nhalf 4";
+        let err = try_run(&vm, source).unwrap_err();
+        
+        // Then: the error has a kind, and the stack lists the failing command first
+        assert_eq!(ErrorKind::Arithmetic, err.kind);
+        let names: Vec<&str> = err.stack.iter().map(|frame| frame.name.as_str()).collect();
+        assert_eq!(vec!["//", "half"], names);
+        assert_eq!("// x 0", &source[err.span().unwrap().start..err.span().unwrap().end]);
+        
+        // Then: it renders with the source it came from
+        assert_eq!(
+            "error[arithmetic]: Division by zero in `//`.\n\
+             let half = [x: Int]{ // x 0 }\n\
+             \x20                    ^^^^^^\n\
+             \x20   in `//` at line 1, column 22\n\
+             \x20   in `half` at line 2, column 1",
+            err.render(FileId::default(), source));
+        
+        // Given: a block that fails
+        // Then: its scope is popped, so later blocks still work
+        assert_eq!(ErrorKind::Arithmetic, fail(&vm, "let y = { // 1 0 }"));
+        assert_eq!(int(2), run(&vm, "let z = { + 1 1 }"));
+    }
+    
+    #[test]
+    fn tail_calls_run_in_constant_space() {
+        // Given: a procedure whose recursive call is the last thing it does
+        let vm = new_vm();
+        run(&vm, "let count_down = [n: Int]{ if (== n 0) 0 (count_down (- n 1)) }");
+        
+        // When: it recurses a million times
+        // Then: it finishes without running out of stack
+        assert_eq!(int(0), run(&vm, "count_down 1000000"));
+        
+        // When: it fails at the bottom of a thousand calls
+        // Then: the error unwinds only the last call, because each call took its caller's frame
+        run(&vm, "let fail_down = [n: Int]{ if (== n 0) (// 1 0) (fail_down (- n 1)) }");
+        let err = try_run(&vm, "fail_down 1000").unwrap_err();
+        let names: Vec<&str> = err.stack.iter().map(|frame| frame.name.as_str()).collect();
+        assert_eq!(vec!["//", "fail_down"], names);
+        
+        // Given: a procedure that fails in a tail call
+        // Then: the tail call takes its caller's place in the stack
+        run(&vm, "let half = [x: Int]{ // x 0 }");
+        run(&vm, "let call_half = [x: Int]{ half x }");
+        let err = try_run(&vm, "call_half 4").unwrap_err();
+        let names: Vec<&str> = err.stack.iter().map(|frame| frame.name.as_str()).collect();
+        assert_eq!(vec!["//", "half"], names);
+    }
+    
+    #[test]
+    fn deep_recursion_does_not_overflow_the_stack() {
+        // Given: a procedure whose recursive call is not in tail position
+        let vm = new_vm();
+        let global = vm.lock().unwrap().get_current_scope();
+        run(&vm, "let sum = [n: Int]{ if (== n 0) 0 (+ n (sum (- n 1))) }");
+        
+        // Then: it can recurse far deeper than the Rust stack would allow
+        assert_eq!(int(500000500000), run(&vm, "sum 1000000"));
+        
+        // Given: a deep recursion that fails at the bottom
+        // Then: the error unwinds every call, and the VM is back in the global scope
+        run(&vm, "let broken = [n: Int]{ if (== n 0) (// 1 0) (+ n (broken (- n 1))) }");
+        let err = try_run(&vm, "broken 10000").unwrap_err();
+        assert_eq!(ErrorKind::Arithmetic, err.kind);
+        assert_eq!(20002, err.stack.len());
+        assert!(Arc::ptr_eq(&global, &vm.lock().unwrap().get_current_scope()));
+    }
+    
+    #[test]
+    fn shift_captures_the_continuation_up_to_reset() {
+        let vm = new_vm();
+        let global = vm.lock().unwrap().get_current_scope();
+        
+        // Given: a continuation that is resumed twice
+        // Then: each resumption runs the rest of the reset again
+        assert_eq!(int(121), run(&vm, "+ 1 (reset (+ 10 (shift [k: Any]{ k (k 100) })))"));
+        
+        // Given: a shift that never resumes its continuation
+        // Then: its value skips the rest of the reset
+        assert_eq!(int(5), run(&vm, "reset (+ 1 (shift [k: Any]{ let unused = 5 }))"));
+        
+        // Given: a continuation that escapes its reset
+        // Then: it can be resumed later, any number of times
+        run(&vm, "let saved = (reset (+ 1 (shift [k: Any]{ let k = k })))");
+        assert_eq!(int(2), run(&vm, "saved 1"));
+        assert_eq!(int(11), run(&vm, "saved 10"));
+        
+        // Given: a shift inside a block inside a procedure
+        // Then: resuming it restores the scopes it was captured in, and leaves them afterwards
+        run(&vm, "let f = [x: Int]{ let z = { let y = (+ x 1); shift [k: Any]{ k y } } }");
+        assert_eq!(int(5), run(&vm, "reset (+ 1 (f 3))"));
+        assert!(Arc::ptr_eq(&global, &vm.lock().unwrap().get_current_scope()));
+    }
+    
+    #[test]
+    fn try_handles_raised_values_and_errors() {
+        let vm = new_vm();
+        
+        // Given: a raised value or a failing native
+        // Then: the handler gets the value, or the kind and message of the error
+        assert_eq!(int(43), run(&vm, "try (raise 42) handle: [c: Any]{ + c 1 }"));
+        assert_eq!(
+            Value::Primitive(PrimitiveData::Name("arithmetic".to_owned())),
+            run(&vm, "try (// 1 0) handle: [c: Any]{ field c kind }"));
+        
+        // Given: a body that does not fail
+        // Then: the handler is not called
+        assert_eq!(int(2), run(&vm, "try (+ 1 1) handle: [c: Any]{ raise c }"));
+        
+        // Given: a raise without a handler
+        // Then: it is an error that carries the raised value
+        let err = try_run(&vm, "raise 42").unwrap_err();
+        assert_eq!(ErrorKind::Raised, err.kind);
+        assert_eq!(Some(int(42)), err.value);
+    }
+    
+    #[test]
+    fn signals_are_resumable() {
+        // Given: a computation that asks for two values
+        let vm = new_vm();
+        run(&vm, "let ask = []{ + (signal \"x?\") (signal \"y?\") }");
+        
+        // When: the handler resumes every signal
+        // Then: the computation finishes with the values it was given
+        assert_eq!(int(20), run(&vm, "try (ask) handle: [c: Any k: Any]{ resume k 10 }"));
+        
+        // When: the handler suspends the computation instead, like a wizard waiting for input
+        // Then: it can be resumed step by step, and from the same step more than once
+        run(&vm, "let first = (try (ask) handle: [c: Any k: Any]{ let k = k })");
+        run(&vm, "let second = (resume first 1)");
+        assert_eq!(int(3), run(&vm, "resume second 2"));
+        assert_eq!(int(6), run(&vm, "resume second 5"));
+        
+        // Given: a signal without a handler
+        // Then: it fails like a raise
+        assert_eq!(ErrorKind::Raised, fail(&vm, "signal 1"));
+    }
+    
+    #[test]
+    fn registered_closures() {
+        // Given: a typed Rust closure registered as a native
+        let vm = new_vm();
+        vm.lock().unwrap().register("add", |a: i64, b: i64| a + b);
+        run(&vm, "let x = 2");
+        
+        // Then: its arguments are looked up and converted, and its result is converted back
+        assert_eq!(int(3), run(&vm, "add 1 x"));
+        
+        // When: it gets the wrong number or the wrong kind of arguments
+        // Then: it fails before the closure runs
+        assert_eq!(ErrorKind::Arity, fail(&vm, "add 1"));
+        assert_eq!(ErrorKind::TypeMismatch, fail(&vm, "add 1 \"two\""));
+        
+        // Given: a closure that captures state and can fail
+        let count = Arc::new(AtomicUsize::new(0));
+        let counter = count.clone();
+        vm.lock().unwrap().register("tick", move |by: usize| {
+            if by == 0 {
+                return Err(ShockError::new(ErrorKind::Arithmetic, "Cannot tick by zero."));
+            }
+            Ok(counter.fetch_add(by, AtomicOrdering::SeqCst) + by)
+        });
+        
+        // Then: the state is shared between calls and with the host
+        assert_eq!(int(1), run(&vm, "tick 1"));
+        assert_eq!(int(3), run(&vm, "tick 2"));
+        assert_eq!(ErrorKind::Arithmetic, fail(&vm, "tick 0"));
+        assert_eq!(3, count.load(AtomicOrdering::SeqCst));
+        
+        // Given: a closure over lists and strings
+        vm.lock().unwrap().register("join", |parts: Vec<String>| parts.join(" "));
+        assert_eq!(
+            Value::Primitive(PrimitiveData::String("a b".to_owned())),
+            run(&vm, "join [\"a\", \"b\"]"));
+        assert_eq!(ErrorKind::TypeMismatch, fail(&vm, "join [\"a\", 1]"));
+    }
+    
+    #[test]
+    fn unknown_procedures_and_non_procedures() {
+        let vm = new_vm();
+        run(&vm, "let x = 1");
+        assert_eq!(ErrorKind::UnboundName, fail(&vm, "nope 1"));
+        assert_eq!(ErrorKind::NotApplicable, fail(&vm, "x 1"));
+        assert_eq!(ErrorKind::InvalidDeclaration, fail(&vm, "let f = [x: Int x: Int]{ + x 1 }"));
+    }
+}
@@ -7,3 +7,8 @@ pub mod parser;
 pub mod interpreter;
 pub mod primitive;
 pub mod placemodel;
+pub mod engine;
+pub mod trace;
+pub mod editor;
+
+pub use engine::Engine;
@@ -1,16 +1,16 @@
 extern crate shock;
 extern crate rustyline;
 
-use shock::parser::{ExpressionValue, primitive_value, boolean, integer_decimal, parse,
-                    vec_to_string};
+use shock::parser::{primitive_value, boolean, integer_decimal, vec_to_string};
 use shock::model::{PrimitiveData, PlaceData, Place};
-use shock::interpreter::{VM, VMScope, Value, eval};
+use shock::Engine;
+use shock::trace::Tracer;
 
 use rustyline::error::ReadlineError;
 use rustyline::Editor;
 use std::collections::HashMap;
-use std::sync::Mutex;
-use std::sync::Arc;
+use std::io;
+use std::sync::{Arc, Mutex};
 
 #[macro_use]
 extern crate nom;
@@ -31,36 +31,32 @@ fn main() {
         println!("Loaded history.");
     }
     
-    let mut vm = Arc::new(Mutex::new(VM {
-        curr_scope: Arc::new(Mutex::new(VMScope {
-            vars: Arc::new(Mutex::new(HashMap::new())),
-            args: Vec::new(),
-            acc: Value::Unit,
-            parent: None,
-            child: None,
-        })),
-        curr_expr: ExpressionValue::Unit,
-    }));
-    vm.lock().unwrap().define_standard_functions();
+    let mut engine = Engine::new();
+    let mut tracing = false;
     
     loop {
         let mut line = editor.readline(">> ");
         
         match &mut line {
             Ok(line) => {
-                editor.add_history_entry(line.as_ref());
-                line.push('\n');
-                line.push('\n');
-                let result = parse(&line).map(
-                    |val| { val.1 }
-                );
-//                println!("PARSED: {:?}", result);
-                let result = result.unwrap();
-                let value = match result.get(0) {
-                    None => &ExpressionValue::Unit,
-                    Some(val) => val,
-                };
-                println!("<< {:?}", eval(&vm, value));
+                editor.add_history_entry(line.as_str());
+                // `:trace` turns printing every expression and its value on or off.
+                if line.trim() == ":trace" {
+                    tracing = !tracing;
+                    engine.set_observer(if tracing {
+                        Some(Arc::new(Mutex::new(Tracer::new(Arc::new(Mutex::new(io::stdout()))))))
+                    } else {
+                        None
+                    });
+                    println!("Tracing is {}.", if tracing { "on" } else { "off" });
+                    continue;
+                }
+                // The engine gives each line its own file id, so errors can tell which line a
+                // span belongs to.
+                match engine.eval_str(&line) {
+                    Ok(value) => println!("<< {:?}", value),
+                    Err(err) => println!("{}", engine.render(&err)),
+                }
             },
             Err(ReadlineError::Interrupted) => {
                 println!("CTRL-C");
@@ -4,15 +4,7 @@ use uuid::Uuid;
 use std::collections::HashMap;
 use std::fmt;
 
-#[derive(Debug, Clone, PartialEq)]
-pub enum PrimitiveData {
-    Bool(bool),
-    Byte(u8),
-    Int(i64),
-    Float(f64),
-    String(String),
-    Name(String),
-}
+pub use crate::primitive::types::PrimitiveData;
 
 #[derive(Debug, Clone)]
 pub enum PlaceData {
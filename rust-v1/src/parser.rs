@@ -1,9 +1,209 @@
-use nom::{take_while1, is_digit, anychar};
+use nom::{take_while1, is_digit, is_hex_digit, is_oct_digit, anychar, IResult};
 
 use crate::model::{PrimitiveData};
 
-use nom::{digit, is_space};
+use nom::{is_space, multispace, Context, ErrorKind};
 use std::prelude::v1::Vec;
+use std::error::Error;
+use std::fmt;
+use std::ops::Range;
+
+/// Grammar rules that report themselves by name when parsing fails inside of them.
+///
+/// Once a rule has consumed its opening token (e.g. `[` or `{`), failures inside it are not
+/// backtracked over, so the error points at the exact place the rule broke.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Rule {
+    Expression = 1,
+    EndOfCommand,
+    CommandExpression,
+    ArgumentList,
+    BlockExpression,
+    String,
+    NumberInRange,
+    Comment,
+    StructComment,
+    ListExpression,
+    StructExpression,
+    MalformedNumber,
+}
+
+impl Rule {
+    /// Recovers a rule from the custom error code it was reported with.
+    pub fn from_code(code: u32) -> Option<Rule> {
+        match code {
+            1 => Some(Rule::Expression),
+            2 => Some(Rule::EndOfCommand),
+            3 => Some(Rule::CommandExpression),
+            4 => Some(Rule::ArgumentList),
+            5 => Some(Rule::BlockExpression),
+            6 => Some(Rule::String),
+            7 => Some(Rule::NumberInRange),
+            8 => Some(Rule::Comment),
+            9 => Some(Rule::StructComment),
+            10 => Some(Rule::ListExpression),
+            11 => Some(Rule::StructExpression),
+            12 => Some(Rule::MalformedNumber),
+            _ => None,
+        }
+    }
+
+    /// The custom error code used to report this rule.
+    pub fn code(self) -> ErrorKind {
+        ErrorKind::Custom(self as u32)
+    }
+
+    /// The name of the rule, as it is shown to the user.
+    pub fn name(self) -> &'static str {
+        match self {
+            Rule::Expression => "expression",
+            Rule::EndOfCommand => "end_of_command",
+            Rule::CommandExpression => "command_expression",
+            Rule::ArgumentList => "argument_list",
+            Rule::BlockExpression => "block_expression",
+            Rule::String => "string",
+            Rule::NumberInRange => "number_in_range",
+            Rule::Comment => "comment",
+            Rule::StructComment => "struct_comment",
+            Rule::ListExpression => "list_expression",
+            Rule::StructExpression => "struct_expression",
+            Rule::MalformedNumber => "malformed_number",
+        }
+    }
+}
+
+/// A located parse failure.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+    /// 1-based line of the failure.
+    pub line: usize,
+    /// 1-based column (in characters) of the failure.
+    pub column: usize,
+    /// Byte span of the offending input.
+    pub span: Range<usize>,
+    /// The rule that was expected at the failure.
+    pub expected: Rule,
+    /// The offending source line with the span underlined by carets.
+    pub snippet: String,
+}
+
+impl ParseError {
+    /// Locates an error at `span` within `source`.
+    pub fn new(source: &str, span: Range<usize>, expected: Rule) -> ParseError {
+        let start = span.start.min(source.len());
+        let end = span.end.min(source.len()).max(start);
+        let (line, column, snippet) = locate(source, start..end);
+        ParseError {
+            line,
+            column,
+            span: start..end,
+            expected,
+            snippet,
+        }
+    }
+
+    /// Converts a nom error produced while parsing `buffer` into a located error in `source`.
+    ///
+    /// `buffer` must begin with `source`; anything past it is treated as the end of input.
+    fn from_nom(source: &str, buffer: &[u8], err: nom::Err<&[u8]>) -> ParseError {
+        let (remaining, expected) = match err {
+            nom::Err::Incomplete(_) => (&buffer[buffer.len()..], Rule::Expression),
+            nom::Err::Error(context) | nom::Err::Failure(context) => match context {
+                Context::Code(input, kind) => (input, rule_of(&kind).unwrap_or(Rule::Expression)),
+                Context::List(list) => (
+                    list.first().map_or(&buffer[buffer.len()..], |entry| entry.0),
+                    list.iter().filter_map(|entry| rule_of(&entry.1)).next()
+                        .unwrap_or(Rule::Expression),
+                ),
+            },
+        };
+        let start = buffer.len() - remaining.len();
+        let token_len = remaining.iter()
+            .take_while(|chr| !chr.is_ascii_whitespace() && !b"()[]{};".contains(chr))
+            .count()
+            .max(1);
+        ParseError::new(source, start..start + token_len, expected)
+    }
+}
+
+/// Finds the line and column where `span` starts in `source`, along with a snippet of that line
+/// with the span underlined.
+pub fn locate(source: &str, span: Range<usize>) -> (usize, usize, String) {
+    let start = span.start.min(source.len());
+    let end = span.end.min(source.len()).max(start);
+    let line_start = source[..start].rfind('\n').map_or(0, |i| i + 1);
+    let line_end = source[start..].find('\n').map_or(source.len(), |i| start + i);
+    let line_text = &source[line_start..line_end];
+    let column = source[line_start..start].chars().count() + 1;
+    let width = source[start..end.min(line_end)].chars().count().max(1);
+    let snippet = format!("{}\n{}{}", line_text, " ".repeat(column - 1), "^".repeat(width));
+    (source[..start].matches('\n').count() + 1, column, snippet)
+}
+
+fn rule_of(kind: &ErrorKind) -> Option<Rule> {
+    match kind {
+        ErrorKind::Custom(code) => Rule::from_code(*code),
+        _ => None,
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "error: expected {} at line {}, column {}\n{}",
+               self.expected.name(), self.line, self.column, self.snippet)
+    }
+}
+
+impl Error for ParseError {}
+
+/// Identifies the source file that a span points into.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct FileId(pub usize);
+
+/// A byte range of source code that an AST node was parsed from.
+///
+/// While parsing, the individual parsers only see the input that is left, so spans are first
+/// recorded as distances from the end of the input. `parse` turns them into offsets from the
+/// start of the source before handing out the AST.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Span {
+    pub file: FileId,
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    /// The (still unresolved) span of input consumed between `before` and `after`.
+    pub fn consumed(before: &[u8], after: &[u8]) -> Span {
+        Span { file: FileId::default(), start: before.len(), end: after.len() }
+    }
+    
+    /// Turns a span recorded while parsing into offsets within a source of `len` bytes.
+    fn resolve(&mut self, len: usize, file: FileId) {
+        self.file = file;
+        self.start = len.saturating_sub(self.start);
+        self.end = len.saturating_sub(self.end);
+    }
+}
+
+impl fmt::Display for Span {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}..{}", self.start, self.end)
+    }
+}
+
+/// Runs a parser and pairs its output with the span of input it consumed.
+macro_rules! spanned (
+    ($i:expr, $submac:ident!( $($args:tt)* )) => (
+        match $submac!($i, $($args)*) {
+            Ok((rest, value)) => Ok((rest, (value, Span::consumed($i, rest)))),
+            Err(err) => Err(err),
+        }
+    );
+    ($i:expr, $f:expr) => (
+        spanned!($i, call!($f))
+    );
+);
 
 /// Verifies that a character is an identifier character.
 pub fn is_identifier_char(chr: u8) -> bool {
@@ -53,9 +253,9 @@ named!(pub identifier<String>,
 named!(pub string<String>,
     do_parse!(
         tag!("\"") >>
-        string_contents: take_until!("\"") >>
-        tag!("\"")
-        >>
+        string_contents: return_error!(Rule::String.code(), complete!(
+            terminated!(take_until!("\""), tag!("\""))
+        )) >>
         (vec_to_string(string_contents))
     )
 );
@@ -89,27 +289,125 @@ named!(pub integer<String>,
 );
 
 
-#[derive(Debug, Clone, PartialEq, Default)]
+/// A named command applied to its arguments.
+///
+/// Equality ignores the span, so structurally identical commands compare equal wherever they
+/// were parsed from.
+#[derive(Debug, Clone, Default)]
 pub struct Command {
     pub name: String,
     pub args: Vec<(String, ExpressionValue)>,
+    pub span: Span,
+    /// Entries of the structured `@( ... )@` comments written before the command.
+    pub meta: Metadata,
+}
+
+impl PartialEq for Command {
+    fn eq(&self, other: &Command) -> bool {
+        self.name == other.name && self.args == other.args && self.meta == other.meta
+    }
+}
+
+impl Command {
+    fn resolve_spans(&mut self, len: usize, file: FileId) {
+        self.span.resolve(len, file);
+        for (_, arg) in self.args.iter_mut().chain(self.meta.iter_mut()) {
+            arg.resolve_spans(len, file);
+        }
+    }
 }
 
 pub type ArgumentList = Vec<(String, ExpressionValue)>;
+pub type Metadata = Vec<(String, ExpressionValue)>;
+pub type StructFields = Vec<(String, ExpressionValue)>;
 pub type ExpressionBlock = Vec<Command>;
 pub type PathComponents = Vec<String>;
 
 
-#[derive(Debug, Clone, PartialEq)]
+/// An expression in the AST. Every variant parsed from source carries the span it came from;
+/// like `Command`, equality ignores spans.
+#[derive(Debug, Clone)]
 pub enum ExpressionValue {
-    Primitive(PrimitiveData),
+    Primitive(PrimitiveData, Span),
     Expression(Command),
-    Block(ExpressionBlock),
-    Procedure(ArgumentList, ExpressionBlock),
-    Path(PathComponents),
+    Block(ExpressionBlock, Span),
+    Procedure(ArgumentList, ExpressionBlock, Span),
+    Path(PathComponents, Span),
+    List(Vec<ExpressionValue>, Span),
+    Struct(StructFields, Span),
     Unit,
 }
 
+impl PartialEq for ExpressionValue {
+    fn eq(&self, other: &ExpressionValue) -> bool {
+        use self::ExpressionValue::*;
+        match (self, other) {
+            (Primitive(a, _), Primitive(b, _)) => a == b,
+            (Expression(a), Expression(b)) => a == b,
+            (Block(a, _), Block(b, _)) => a == b,
+            (Procedure(a_args, a_body, _), Procedure(b_args, b_body, _)) =>
+                a_args == b_args && a_body == b_body,
+            (Path(a, _), Path(b, _)) => a == b,
+            (List(a, _), List(b, _)) => a == b,
+            (Struct(a, _), Struct(b, _)) => a == b,
+            (Unit, Unit) => true,
+            _ => false,
+        }
+    }
+}
+
+impl ExpressionValue {
+    /// The span of source this expression was parsed from. `Unit` has no source.
+    pub fn span(&self) -> Span {
+        match self {
+            ExpressionValue::Primitive(_, span) |
+            ExpressionValue::Block(_, span) |
+            ExpressionValue::Procedure(_, _, span) |
+            ExpressionValue::Path(_, span) |
+            ExpressionValue::List(_, span) |
+            ExpressionValue::Struct(_, span) => *span,
+            ExpressionValue::Expression(command) => command.span,
+            ExpressionValue::Unit => Span::default(),
+        }
+    }
+    
+    fn resolve_spans(&mut self, len: usize, file: FileId) {
+        match self {
+            ExpressionValue::Primitive(_, span) |
+            ExpressionValue::Path(_, span) => span.resolve(len, file),
+            ExpressionValue::Block(body, span) => {
+                span.resolve(len, file);
+                for command in body.iter_mut() {
+                    command.resolve_spans(len, file);
+                }
+            },
+            ExpressionValue::Procedure(args, body, span) => {
+                span.resolve(len, file);
+                for (_, arg) in args.iter_mut() {
+                    arg.resolve_spans(len, file);
+                }
+                for command in body.iter_mut() {
+                    command.resolve_spans(len, file);
+                }
+            },
+            ExpressionValue::List(elements, span) => {
+                span.resolve(len, file);
+                for element in elements.iter_mut() {
+                    element.resolve_spans(len, file);
+                }
+            },
+            ExpressionValue::Struct(fields, span) => {
+                span.resolve(len, file);
+                for (_, field) in fields.iter_mut() {
+                    field.resolve_spans(len, file);
+                }
+            },
+            ExpressionValue::Expression(command) => command.resolve_spans(len, file),
+            ExpressionValue::Unit => {},
+        }
+    }
+}
+
 pub fn is_linespace(chr: u8) -> bool { chr == b' ' || chr == b'\t' }
 
 named!(pub space, take_while1!(is_space));
@@ -120,21 +418,176 @@ named!(pub keyword_delete, alt!(tag!("delete") | tag!("del")));
 named!(pub keyword_put, alt!(tag!("put")));
 named!(pub keyword_focus, alt!(tag!("focus") | tag!("fs")));
 
+/// Numeric literals may separate their digits with underscores, e.g. `1_000_000`.
+pub fn is_digit_or_separator(chr: u8) -> bool { is_digit(chr) || chr == b'_' }
+pub fn is_hex_digit_or_separator(chr: u8) -> bool { is_hex_digit(chr) || chr == b'_' }
+pub fn is_oct_digit_or_separator(chr: u8) -> bool { is_oct_digit(chr) || chr == b'_' }
+pub fn is_bin_digit_or_separator(chr: u8) -> bool { chr == b'0' || chr == b'1' || chr == b'_' }
+
+/// Whether every `_` in a numeric literal is between two digits of its radix, e.g. not in `1_`,
+/// `1__0` or `0x_1`.
+fn well_separated(literal: &[u8], radix: u32) -> bool {
+    let is_digit = |chr: Option<&u8>| chr.map_or(false, |chr| (*chr as char).is_digit(radix));
+    literal.iter().enumerate()
+        .filter(|(_, chr)| **chr == b'_')
+        .all(|(i, _)| i > 0 && is_digit(literal.get(i - 1)) && is_digit(literal.get(i + 1)))
+}
+
+/// Removes the `_` separators from the digits of a numeric literal.
+fn strip_separators(digits: &[u8]) -> String {
+    vec_to_string(digits).replace('_', "")
+}
+
 named!(pub sign, recognize!(opt!(one_of!("+-"))));
-named!(pub integer_decimal_literal, recognize!(do_parse!(sign >> digit >> ())));
+named!(negative<bool>, map!(sign, |s| s == b"-"));
+
+// Decimal digits, starting with a digit and optionally separated by underscores.
+named!(pub decimal_digits<String>,
+    map!(
+        recognize!(pair!(take_while_m_n!(1, 1, is_digit), take_while!(is_digit_or_separator))),
+        strip_separators
+    )
+);
+
+named!(pub integer_decimal_literal, recognize!(pair!(sign, decimal_digits)));
 named!(pub integer_decimal<i64>,
-    map_res!(
-        map_res!(integer_decimal_literal, std::str::from_utf8),
-        |s| i64::from_str_radix(s, 10)
+    map_opt!(
+        pair!(negative, decimal_digits),
+        |(negative, digits): (bool, String)|
+            NumericLiteral::integer(negative, 10, digits, None).value()
+                .and_then(|value| match value {
+                    PrimitiveData::Int(i) => Some(i),
+                    _ => None,
+                })
     )
 );
-named!(pub boolean<bool>, alt!(
-    tag!("true") => { |_| true } |
-    tag!("false") => { |_| false }
+
+/// The lexical pieces of a numeric literal, before its value is computed.
+#[derive(Debug, Clone, PartialEq)]
+struct NumericLiteral {
+    negative: bool,
+    radix: u32,
+    /// The digits with separators removed; floats keep their fraction and exponent.
+    digits: String,
+    is_float: bool,
+    suffix: Option<String>,
+}
+
+impl NumericLiteral {
+    fn integer(negative: bool, radix: u32, digits: String, suffix: Option<&[u8]>) -> Self {
+        NumericLiteral { negative, radix, digits, is_float: false, suffix: suffix.map(vec_to_string) }
+    }
+    
+    /// Computes the value of the literal, or `None` if it does not fit its type.
+    fn value(&self) -> Option<PrimitiveData> {
+        if self.is_float {
+            let sign = if self.negative { "-" } else { "" };
+            return format!("{}{}", sign, self.digits).parse::<f64>().ok()
+                .filter(|value| value.is_finite())
+                .map(PrimitiveData::Float);
+        }
+        let magnitude = u64::from_str_radix(&self.digits, self.radix).ok()?;
+        if self.negative && magnitude != 0 && self.suffix.is_some() {
+            return None;
+        }
+        match self.suffix.as_deref() {
+            None if self.negative => {
+                if magnitude <= i64::MAX as u64 + 1 {
+                    Some(PrimitiveData::Int((magnitude as i64).wrapping_neg()))
+                } else {
+                    None
+                }
+            },
+            None => {
+                if magnitude <= i64::MAX as u64 {
+                    Some(PrimitiveData::Int(magnitude as i64))
+                } else {
+                    None
+                }
+            },
+            Some("u") => Some(PrimitiveData::Unsigned(magnitude)),
+            Some("u8") => {
+                if magnitude <= u64::from(u8::MAX) {
+                    Some(PrimitiveData::Byte(magnitude as u8))
+                } else {
+                    None
+                }
+            },
+            Some(_) => None,
+        }
+    }
+}
+
+named!(integer_suffix, alt_complete!(tag!("u8") | tag!("u")));
+
+named!(radix_integer<NumericLiteral>,
+    do_parse!(
+        negative: negative >>
+        radix_digits: alt_complete!(
+            preceded!(tag!("0x"), take_while!(is_hex_digit_or_separator)) => { |d| (16, d) } |
+            preceded!(tag!("0o"), take_while!(is_oct_digit_or_separator)) => { |d| (8, d) } |
+            preceded!(tag!("0b"), take_while!(is_bin_digit_or_separator)) => { |d| (2, d) }
+        ) >>
+        suffix: opt!(integer_suffix) >>
+        (NumericLiteral::integer(negative, radix_digits.0, strip_separators(radix_digits.1), suffix))
+    )
+);
+
+named!(decimal_number<NumericLiteral>,
+    do_parse!(
+        negative: negative >>
+        integral: decimal_digits >>
+        fraction: opt!(complete!(preceded!(char!('.'), decimal_digits))) >>
+        exponent: opt!(complete!(preceded!(one_of!("eE"), pair!(sign, decimal_digits)))) >>
+        suffix: cond!(fraction.is_none() && exponent.is_none(), opt!(integer_suffix)) >>
+        ({
+            match (fraction, exponent) {
+                (None, None) => NumericLiteral::integer(negative, 10, integral, suffix.and_then(|s| s)),
+                (fraction, exponent) => {
+                    let mut digits = integral;
+                    if let Some(fraction) = fraction {
+                        digits = digits + "." + &fraction;
+                    }
+                    if let Some((exponent_sign, exponent_digits)) = exponent {
+                        digits = digits + "e" + &vec_to_string(exponent_sign) + &exponent_digits;
+                    }
+                    NumericLiteral { negative, radix: 10, digits, is_float: true, suffix: None }
+                },
+            }
+        })
+    )
+);
+
+/// Parses a numeric literal:
+///
+/// - decimal integers, e.g. `42`, `-7`, `1_000_000`
+/// - hexadecimal, octal and binary integers, e.g. `0xff`, `0o755`, `0b1010`
+/// - floats with a fraction and/or an exponent, e.g. `3.14`, `1e-9`
+///
+/// Integers are `Int`s, unless suffixed with `u` (`Unsigned`) or `u8` (`Byte`). A literal that
+/// does not fit its type fails the parse instead of being truncated, and so does a literal with
+/// no digits after its prefix or with an `_` that does not separate two digits.
+pub fn numeric_literal(input: &[u8]) -> IResult<&[u8], PrimitiveData> {
+    let (rest, literal) = alt_complete!(input, radix_integer | decimal_number)?;
+    let lexeme = &input[..input.len() - rest.len()];
+    if literal.digits.is_empty() || !well_separated(lexeme, literal.radix) {
+        return Err(nom::Err::Failure(Context::Code(input, Rule::MalformedNumber.code())));
+    }
+    match literal.value() {
+        Some(value) => Ok((rest, value)),
+        None => Err(nom::Err::Failure(Context::Code(input, Rule::NumberInRange.code()))),
+    }
+}
+named!(pub boolean<bool>, terminated!(
+    alt!(
+        tag!("true") => { |_| true } |
+        tag!("false") => { |_| false }
+    ),
+    not!(take_while_m_n!(1, 1, is_identifier_char))
 ));
 
-named!(pub operator_initial<char>, one_of!(",><+-=|^%~?*/"));
-named!(pub operator_subsequent<char>, one_of!(":,.><+-=|^%~?"));
+named!(pub operator_initial<char>, one_of!(",><+-=|^%~?*/!"));
+named!(pub operator_subsequent<char>, one_of!(":,.><+-=|^%~?/"));
 named!(pub operator_identifier<String>,
     do_parse!(
         initial: many_m_n!(1, 1, operator_initial) >>
@@ -166,9 +619,9 @@ named!(pub
 );
 named!(pub primitive_value<PrimitiveData>,
     alt_complete!(
-        identifier          => { |n| PrimitiveData::Name(n) } |
-        integer_decimal     => { |i| PrimitiveData::Int(i) } |
+        numeric_literal     => { |n| n } |
         boolean             => { |b| PrimitiveData::Bool(b) } |
+        identifier          => { |n| PrimitiveData::Name(n) } |
         string              => { |s| PrimitiveData::String(s) }
     )
 );
@@ -176,10 +629,12 @@ named!(pub primitive_value<PrimitiveData>,
 named!(pub expression_value<ExpressionValue>,
     alt_complete!(
         procedure_expression => { |val| val } |
-        block_expression => { |val| ExpressionValue::Block(val) } |
+        spanned!(list_expression) => { |(val, span)| ExpressionValue::List(val, span) } |
+        spanned!(block_expression) => { |(val, span)| ExpressionValue::Block(val, span) } |
+        spanned!(struct_expression) => { |(val, span)| ExpressionValue::Struct(val, span) } |
         command_expression => { |val| ExpressionValue::Expression(val) } |
-        path_expression => { |val| ExpressionValue::Path(val) } |
-        primitive_value => { |val| ExpressionValue::Primitive(val) }
+        spanned!(path_expression) => { |(val, span)| ExpressionValue::Path(val, span) } |
+        spanned!(primitive_value) => { |(val, span)| ExpressionValue::Primitive(val, span) }
     )
 );
 
@@ -205,24 +660,134 @@ named!(pub command_arguments<Vec<(String, ExpressionValue)>>,
 );
 
 named!(pub argument_list<Vec<(String, ExpressionValue)>>,
-    delimited!(
+    preceded!(
+        char!('['),
+        return_error!(Rule::ArgumentList.code(), complete!(terminated!(
+            many0!(preceded!(opt!(linespace), command_argument_pair)),
+            preceded!(opt!(linespace), char!(']'))
+        )))
+    ));
+
+// Elements of lists and structs are separated by commas, and may be spread over several lines.
+named!(pub element_separator, delimited!(opt!(multispace), tag!(","), opt!(multispace)));
+
+// `[a, b, c]`
+named!(pub list_expression<Vec<ExpressionValue>>,
+    preceded!(
         char!('['),
-        many0!(preceded!(opt!(linespace), command_argument_pair)),
-        char!(']')
+        return_error!(Rule::ListExpression.code(), complete!(terminated!(
+            separated_list!(element_separator, preceded!(opt!(multispace), expression_value)),
+            tuple!(opt!(element_separator), opt!(multispace), char!(']'))
+        )))
+    ));
+
+named!(pub struct_field<(String, ExpressionValue)>,
+    do_parse!(
+        name: terminated!(identifier, char!(':')) >>
+        opt!(linespace) >>
+        value: expression_value >>
+        ((name, value))
+    ));
+
+// Distinguishes `(name: value, ...)` and `()` from a command expression.
+named!(struct_start,
+    peek!(recognize!(preceded!(
+        char!('('),
+        preceded!(opt!(multispace), alt_complete!(
+            char!(')') => { |_| () } |
+            terminated!(identifier, char!(':')) => { |_| () }
+        ))
+    ))));
+
+// `(name: expr, ...)`
+named!(pub struct_expression<StructFields>,
+    preceded!(
+        pair!(struct_start, char!('(')),
+        return_error!(Rule::StructExpression.code(), complete!(terminated!(
+            separated_list!(element_separator, preceded!(opt!(multispace), struct_field)),
+            tuple!(opt!(element_separator), opt!(multispace), char!(')'))
+        )))
+    ));
+
+pub fn is_line_end(chr: u8) -> bool { chr == b'\n' || chr == b'\r' }
+pub fn is_metadata_separator(chr: u8) -> bool { chr.is_ascii_whitespace() || chr == b',' }
+
+// `@ ...` runs until the end of the line.
+named!(pub line_comment,
+    preceded!(
+        terminated!(char!('@'), not!(char!('('))),
+        take_till!(is_line_end)
+    ));
+
+// `@@ ... @@` may span multiple lines.
+named!(pub multi_line_comment,
+    preceded!(
+        tag!("@@"),
+        return_error!(Rule::Comment.code(), complete!(terminated!(take_until!("@@"), tag!("@@"))))
+    ));
+
+// `@[ ... ]`
+named!(pub block_comment,
+    preceded!(
+        tag!("@["),
+        return_error!(Rule::Comment.code(), complete!(terminated!(take_until!("]"), tag!("]"))))
+    ));
+
+// `@{ ... }@`
+named!(pub list_comment,
+    preceded!(
+        tag!("@{"),
+        return_error!(Rule::Comment.code(), complete!(terminated!(take_until!("}@"), tag!("}@"))))
+    ));
+
+// `@( label: expr, ... )@` is kept as metadata on the command that follows it.
+named!(pub struct_comment<Metadata>,
+    preceded!(
+        tag!("@("),
+        return_error!(Rule::StructComment.code(), complete!(terminated!(
+            many0!(preceded!(take_while!(is_metadata_separator), command_argument_pair)),
+            preceded!(take_while!(is_metadata_separator), tag!(")@"))
+        )))
+    ));
+
+// Comments that carry no metadata.
+named!(pub inline_comment,
+    alt_complete!(multi_line_comment | block_comment | list_comment | line_comment));
+
+named!(pub comment<Option<Metadata>>,
+    alt_complete!(
+        struct_comment => { |meta| Some(meta) } |
+        inline_comment => { |_| None }
+    ));
+
+// Comments before a command, each followed by any amount of whitespace.
+named!(pub leading_comments<Metadata>,
+    map!(
+        many0!(terminated!(preceded!(opt!(linespace), comment), opt!(multispace))),
+        |comments: Vec<Option<Metadata>>| comments.into_iter().flatten().flatten().collect()
     ));
 
 named!(pub command<Command>, do_parse!(
+    meta: leading_comments >>
     opt!(linespace) >>
-    command_name: alt!(identifier | operator_identifier)  >>
-    opt!(linespace) >>
-    arguments: command_arguments >>
+    parts: spanned!(pair!(
+        alt!(identifier | operator_identifier),
+        preceded!(opt!(linespace), command_arguments)
+    )) >>
     opt!(linespace) >>
-    peek!(one_of!("\r\n;)}")) >>
-    (Command {name: command_name, args: arguments})
+    opt!(inline_comment) >>
+    return_error!(Rule::EndOfCommand.code(), complete!(peek!(one_of!("\r\n;)}")))) >>
+    ({
+        let ((name, args), span) = parts;
+        Command { name, args, span, meta }
+    })
 ));
 
 named!(pub command_expression<Command>,
-    delimited!(char!('('), command, char!(')'))
+    preceded!(
+        char!('('),
+        return_error!(Rule::CommandExpression.code(), complete!(terminated!(command, char!(')'))))
+    )
 );
 
 named!(pub commands<Vec<Command>>, many0!(
@@ -251,15 +816,39 @@ named!(pub block_commands<Vec<Command>>,
 );
 
 named!(pub block_expression<Vec<Command>>,
-    delimited!(char!('{'), block_commands, char!('}')));
+    preceded!(
+        char!('{'),
+        return_error!(Rule::BlockExpression.code(), complete!(terminated!(
+            block_commands,
+            char!('}')
+        )))
+    ));
+
+// Distinguishes `[name: Type ...]{ ... }` from a list literal.
+named!(procedure_start,
+    peek!(recognize!(preceded!(
+        char!('['),
+        preceded!(opt!(linespace), alt_complete!(
+            terminated!(identifier, char!(':')) => { |_| () } |
+            terminated!(operator_identifier, linespace) => { |_| () } |
+            tuple!(char!(']'), opt!(linespace), char!('{')) => { |_| () }
+        ))
+    ))));
 
 named!(pub procedure_expression<ExpressionValue>,
     do_parse!(
         opt!(linespace) >>
-        args: argument_list >>
-        opt!(linespace) >>
-        body: block_expression >>
-        (ExpressionValue::Procedure(args, body))
+        procedure_start >>
+        procedure: spanned!(do_parse!(
+            args: argument_list >>
+            opt!(linespace) >>
+            body: return_error!(Rule::BlockExpression.code(), block_expression) >>
+            ((args, body))
+        )) >>
+        ({
+            let ((args, body), span) = procedure;
+            ExpressionValue::Procedure(args, body, span)
+        })
     )
 );
 
@@ -284,12 +873,55 @@ named!(pub path_expression<Vec<String>>,
 );
 
 
-pub fn parse(line: &str)
-    -> Result<(&[u8], Vec<ExpressionValue>), nom::Err<&[u8]>>
-{
-    expressions(line.as_bytes())
+pub fn is_separator(chr: u8) -> bool { chr.is_ascii_whitespace() || chr == b';' }
+
+/// Skips whitespace, command separators and comments between top-level expressions. Structured
+/// comments are left for the command they belong to.
+fn separators(mut input: &[u8]) -> IResult<&[u8], ()> {
+    loop {
+        let skipped = input.iter().take_while(|&&chr| is_separator(chr)).count();
+        input = &input[skipped..];
+        match inline_comment(input) {
+            Ok((rest, _)) => input = rest,
+            Err(nom::Err::Failure(err)) => return Err(nom::Err::Failure(err)),
+            Err(_) => return Ok((input, ())),
+        }
+    }
+}
+
+/// Parses every expression in the source, failing on the first one that cannot be parsed.
+///
+/// Each expression must be followed by a newline, a `;` or the end of the source.
+pub fn parse(source: &str) -> Result<Vec<ExpressionValue>, ParseError> {
+    parse_file(source, FileId::default())
+}
+
+/// Parses a source file like `parse`, tagging every span in the AST with the file's id.
+pub fn parse_file(source: &str, file: FileId) -> Result<Vec<ExpressionValue>, ParseError> {
+    // Terminate the source so the streaming parsers can see the end of the last command.
+    let buffer = format!("{}\n", source);
+    let buffer = buffer.as_bytes();
+    let to_parse_error = |err| ParseError::from_nom(source, buffer, err);
+    let (mut input, _) = separators(buffer).map_err(to_parse_error)?;
+    let mut values = Vec::new();
+    while !input.is_empty() {
+        let (rest, value) = expression(input).map_err(to_parse_error)?;
+        let (rest, _) = terminated_by_separator(rest).map_err(to_parse_error)?;
+        let (rest, _) = separators(rest).map_err(to_parse_error)?;
+        values.push(value);
+        input = rest;
+    }
+    for value in values.iter_mut() {
+        value.resolve_spans(buffer.len(), file);
+    }
+    Ok(values)
 }
 
+named!(terminated_by_separator<char>,
+    return_error!(Rule::EndOfCommand.code(), complete!(
+        preceded!(pair!(opt!(linespace), opt!(inline_comment)), one_of!("\r\n;"))
+    )));
+
 
 
 #[cfg(test)]
@@ -321,16 +953,69 @@ mod tests {
     #[test]
     fn test_primitive_parsing() {
         use crate::parser::boolean;
+        use crate::parser::primitive_value;
+        use crate::model::PrimitiveData;
         use crate::parser::integer_decimal;
         
         assert_correct_parse!(boolean, "true ", true);
         assert_correct_parse!(boolean, "false ", false);
+        assert_eq!(true, boolean("trueish ".as_bytes()).is_err());
         
         assert_correct_parse!(integer_decimal, "1 ", 1);
         assert_correct_parse!(integer_decimal, "101 ", 101);
         assert_correct_parse!(integer_decimal, "+101 ", 101);
         assert_correct_parse!(integer_decimal, "-101 ", -101);
         assert_correct_parse!(integer_decimal, "-0 ", 0);
+        assert_correct_parse!(integer_decimal, "-12345 ", -12345);
+        assert_correct_parse!(integer_decimal, "1_000 ", 1000);
+        
+        assert_correct_parse!(primitive_value, "true ", PrimitiveData::Bool(true));
+        assert_correct_parse!(primitive_value, "falsey ", PrimitiveData::Name("falsey".to_owned()));
+        assert_correct_parse!(primitive_value, "// ", PrimitiveData::Name("//".to_owned()));
+        assert_correct_parse!(primitive_value, "!= ", PrimitiveData::Name("!=".to_owned()));
+    }
+    
+    #[test]
+    fn test_numeric_literal_parsing() {
+        use crate::parser::numeric_literal;
+        use crate::model::PrimitiveData;
+        
+        assert_correct_parse!(numeric_literal, "42 ", PrimitiveData::Int(42));
+        assert_correct_parse!(numeric_literal, "-42 ", PrimitiveData::Int(-42));
+        assert_correct_parse!(numeric_literal, "1_000_000 ", PrimitiveData::Int(1_000_000));
+        assert_correct_parse!(numeric_literal, "0xff ", PrimitiveData::Int(255));
+        assert_correct_parse!(numeric_literal, "-0xFF ", PrimitiveData::Int(-255));
+        assert_correct_parse!(numeric_literal, "0b1010 ", PrimitiveData::Int(10));
+        assert_correct_parse!(numeric_literal, "0o755 ", PrimitiveData::Int(493));
+        assert_correct_parse!(numeric_literal, "42u ", PrimitiveData::Unsigned(42));
+        assert_correct_parse!(numeric_literal, "0xffu8 ", PrimitiveData::Byte(255));
+        assert_correct_parse!(numeric_literal, "18446744073709551615u ", PrimitiveData::Unsigned(u64::MAX));
+        assert_correct_parse!(numeric_literal, "-9223372036854775808 ", PrimitiveData::Int(i64::MIN));
+        assert_correct_parse!(numeric_literal, "2.5 ", PrimitiveData::Float(2.5));
+        assert_correct_parse!(numeric_literal, "-2.5 ", PrimitiveData::Float(-2.5));
+        assert_correct_parse!(numeric_literal, "1e-9 ", PrimitiveData::Float(1e-9));
+        assert_correct_parse!(numeric_literal, "6.02E23 ", PrimitiveData::Float(6.02e23));
+    }
+    
+    #[test]
+    fn test_numeric_literal_errors() {
+        use crate::parser::{parse, numeric_literal, Rule};
+        
+        for literal in &["9223372036854775808", "256u8", "-1u", "18446744073709551616u", "1e999",
+                         "0x1_0000_0000_0000_0000"] {
+            let err = parse(&format!("let x = {}", literal)).unwrap_err();
+            assert_eq!(Rule::NumberInRange, err.expected, "for {}", literal);
+            assert_eq!(8..8 + literal.len(), err.span, "for {}", literal);
+        }
+        
+        for literal in &["1_", "1__0", "0x_1", "0x", "0b_", "0xu8", "1_u", "1_e5", "2.5_"] {
+            let err = parse(&format!("let x = {}", literal)).unwrap_err();
+            assert_eq!(Rule::MalformedNumber, err.expected, "for {}", literal);
+            assert_eq!(8..8 + literal.len(), err.span, "for {}", literal);
+        }
+        
+        // Not a number at all, so other rules get a chance to parse it.
+        assert!(matches!(numeric_literal(b"- "), Err(nom::Err::Error(_))));
     }
     
     #[test]
@@ -340,37 +1025,38 @@ mod tests {
         use crate::parser::argument_list;
         use crate::parser::ExpressionValue;
         use crate::model::PrimitiveData;
+        use crate::parser::Span;
         
         assert_correct_parse!(
             command_argument_pair,
             "x: Int ",
-            ("x".to_owned(), ExpressionValue::Primitive(PrimitiveData::Name("Int".to_owned())))
+            ("x".to_owned(), ExpressionValue::Primitive(PrimitiveData::Name("Int".to_owned()), Span::default()))
         );
         assert_correct_parse!(
             command_arguments,
             "x: Int \n",
-            vec![("x".to_owned(), ExpressionValue::Primitive(PrimitiveData::Name("Int".to_owned())))]
+            vec![("x".to_owned(), ExpressionValue::Primitive(PrimitiveData::Name("Int".to_owned()), Span::default()))]
         );
         assert_correct_parse!(
             argument_list,
             "[x: Int] ",
-            vec![("x".to_owned(), ExpressionValue::Primitive(PrimitiveData::Name("Int".to_owned())))]
+            vec![("x".to_owned(), ExpressionValue::Primitive(PrimitiveData::Name("Int".to_owned()), Span::default()))]
         );
         assert_correct_parse!(
             argument_list,
             "[x: Int y: String] ",
             vec![
-                ("x".to_owned(), ExpressionValue::Primitive(PrimitiveData::Name("Int".to_owned()))),
-                ("y".to_owned(), ExpressionValue::Primitive(PrimitiveData::Name("String".to_owned())))
+                ("x".to_owned(), ExpressionValue::Primitive(PrimitiveData::Name("Int".to_owned()), Span::default())),
+                ("y".to_owned(), ExpressionValue::Primitive(PrimitiveData::Name("String".to_owned()), Span::default()))
             ]
         );
         assert_correct_parse!(
             argument_list,
             "[x: Int y: String -> String] ",
             vec![
-                ("x".to_owned(), ExpressionValue::Primitive(PrimitiveData::Name("Int".to_owned()))),
-                ("y".to_owned(), ExpressionValue::Primitive(PrimitiveData::Name("String".to_owned()))),
-                ("->".to_owned(), ExpressionValue::Primitive(PrimitiveData::Name("String".to_owned())))
+                ("x".to_owned(), ExpressionValue::Primitive(PrimitiveData::Name("Int".to_owned()), Span::default())),
+                ("y".to_owned(), ExpressionValue::Primitive(PrimitiveData::Name("String".to_owned()), Span::default())),
+                ("->".to_owned(), ExpressionValue::Primitive(PrimitiveData::Name("String".to_owned()), Span::default()))
             ]
         );
     }
@@ -381,6 +1067,7 @@ mod tests {
         use crate::parser::ExpressionValue;
         use crate::parser::Command;
         use crate::model::PrimitiveData;
+        use crate::parser::Span;
         
         assert_correct_parse!(
             command,
@@ -388,17 +1075,21 @@ mod tests {
             Command {
                 name: "let".to_owned(),
                 args: vec![
-                    ("".to_owned(), ExpressionValue::Primitive(PrimitiveData::Name("x".to_owned()))),
+                    ("".to_owned(), ExpressionValue::Primitive(PrimitiveData::Name("x".to_owned()), Span::default())),
                     ("=".to_owned(), ExpressionValue::Expression(
                         Command {
                             name: "+".to_owned(),
                             args: vec![
-                                ("".to_owned(), ExpressionValue::Primitive(PrimitiveData::Int(1))),
-                                ("".to_owned(), ExpressionValue::Primitive(PrimitiveData::Int(1)))
-                            ]
+                                ("".to_owned(), ExpressionValue::Primitive(PrimitiveData::Int(1), Span::default())),
+                                ("".to_owned(), ExpressionValue::Primitive(PrimitiveData::Int(1), Span::default()))
+                            ],
+                            span: Span::default(),
+                            meta: vec![]
                         }
                     ))
-                ]
+                ],
+                span: Span::default(),
+                meta: vec![]
             }
         );
         
@@ -409,14 +1100,17 @@ mod tests {
             Command {
                 name: "let".to_owned(),
                 args: vec![
-                    ("".to_owned(), ExpressionValue::Primitive(PrimitiveData::Name("x".to_owned()))),
+                    ("".to_owned(), ExpressionValue::Primitive(PrimitiveData::Name("x".to_owned()), Span::default())),
                     ("=".to_owned(), ExpressionValue::Procedure(
                         vec![
-                            ("x".to_owned(), ExpressionValue::Primitive(PrimitiveData::Name("Int".to_owned())))
+                            ("x".to_owned(), ExpressionValue::Primitive(PrimitiveData::Name("Int".to_owned()), Span::default()))
                         ],
-                        vec![]
+                        vec![],
+                        Span::default()
                     ))
-                ]
+                ],
+                span: Span::default(),
+                meta: vec![]
             }
         );
     }
@@ -428,6 +1122,7 @@ mod tests {
         use crate::parser::ExpressionValue;
         use crate::parser::Command;
         use crate::model::PrimitiveData;
+        use crate::parser::Span;
         
         assert_correct_parse!(
             expression,
@@ -435,9 +1130,10 @@ mod tests {
             ExpressionValue::Procedure(
                 vec![
                     ("x".to_owned(),
-                        ExpressionValue::Primitive(PrimitiveData::Name("Int".to_owned())))
+                        ExpressionValue::Primitive(PrimitiveData::Name("Int".to_owned()), Span::default()))
                 ],
-                vec![]
+                vec![],
+                Span::default()
             )
         );
         assert_correct_parse!(
@@ -447,17 +1143,192 @@ mod tests {
                 Command {
                     name: "let".to_owned(),
                     args: vec![
-                        ("".to_owned(), ExpressionValue::Primitive(PrimitiveData::Name("x".to_owned()))),
-                        ("=".to_owned(), ExpressionValue::Primitive(PrimitiveData::Int(1)))
-                    ]
+                        ("".to_owned(), ExpressionValue::Primitive(PrimitiveData::Name("x".to_owned()), Span::default())),
+                        ("=".to_owned(), ExpressionValue::Primitive(PrimitiveData::Int(1), Span::default()))
+                    ],
+                    span: Span::default(),
+                    meta: vec![]
                 }
             )
         );
        
     }
+    
+    #[test]
+    fn test_parse() {
+        use crate::parser::parse;
+        use crate::parser::ExpressionValue;
+        use crate::parser::Command;
+        use crate::model::PrimitiveData;
+        use crate::parser::Span;
+        
+        assert_eq!(
+            Ok(vec![
+                ExpressionValue::Expression(Command { name: "show".to_owned(), args: vec![], span: Span::default(), meta: vec![] }),
+                ExpressionValue::Primitive(PrimitiveData::Int(1), Span::default())
+            ]),
+            parse("show; 1")
+        );
+        assert_eq!(Ok(vec![]), parse(" \n; "));
+    }
+    
+    #[test]
+    fn test_list_and_struct_literals() {
+        use crate::parser::{expression_value, parse, Rule, ExpressionValue, Span};
+        use crate::model::PrimitiveData;
+        
+        let int = |i| ExpressionValue::Primitive(PrimitiveData::Int(i), Span::default());
+        let name = |n: &str| ExpressionValue::Primitive(PrimitiveData::Name(n.to_owned()), Span::default());
+        
+        assert_correct_parse!(expression_value, "[] ", ExpressionValue::List(vec![], Span::default()));
+        assert_correct_parse!(
+            expression_value,
+            "[a, 1, [2]] ",
+            ExpressionValue::List(
+                vec![name("a"), int(1), ExpressionValue::List(vec![int(2)], Span::default())],
+                Span::default()
+            )
+        );
+        assert_correct_parse!(
+            expression_value,
+            "[\n    1,\n    2,\n] ",
+            ExpressionValue::List(vec![int(1), int(2)], Span::default())
+        );
+        assert_correct_parse!(expression_value, "() ", ExpressionValue::Struct(vec![], Span::default()));
+        assert_correct_parse!(
+            expression_value,
+            "(x: 1, y: [a]) ",
+            ExpressionValue::Struct(
+                vec![
+                    ("x".to_owned(), int(1)),
+                    ("y".to_owned(), ExpressionValue::List(vec![name("a")], Span::default()))
+                ],
+                Span::default()
+            )
+        );
+        
+        // Procedures and command expressions still parse as before.
+        assert_correct_parse!(
+            expression_value,
+            "[]{} ",
+            ExpressionValue::Procedure(vec![], vec![], Span::default())
+        );
+        match expression_value(b"(+ 1 2) ") {
+            Ok((_, ExpressionValue::Expression(_))) => {},
+            other => panic!("expected a command expression, got {:?}", other),
+        }
+        
+        assert_eq!(Rule::ListExpression, parse("let x = [1 2]").unwrap_err().expected);
+        assert_eq!(Rule::StructExpression, parse("let x = (a: 1 b: 2)").unwrap_err().expected);
+    }
+    
+    #[test]
+    fn test_comments() {
+        use crate::parser::{parse, Rule, ExpressionValue, Command, Span};
+        use crate::model::PrimitiveData;
+        
+        let source = "@ a line comment\n\
+                      show @ trailing\n\
+                      @@ a multi-line\n\
+                      comment @@\n\
+                      @[ a block comment ] show\n\
+                      @{\n    a list comment\n}@\n\
+                      @(\n    name: \"structComment\",\n    type: StructComment,\n)@\n\
+                      let x = 1\n\
+                      @ the end";
+        let parsed = parse(source).unwrap();
+        assert_eq!(3, parsed.len());
+        assert_eq!(
+            ExpressionValue::Expression(Command {
+                name: "let".to_owned(),
+                args: vec![
+                    ("".to_owned(), ExpressionValue::Primitive(PrimitiveData::Name("x".to_owned()), Span::default())),
+                    ("=".to_owned(), ExpressionValue::Primitive(PrimitiveData::Int(1), Span::default()))
+                ],
+                span: Span::default(),
+                meta: vec![
+                    ("name".to_owned(), ExpressionValue::Primitive(PrimitiveData::String("structComment".to_owned()), Span::default())),
+                    ("type".to_owned(), ExpressionValue::Primitive(PrimitiveData::Name("StructComment".to_owned()), Span::default()))
+                ]
+            }),
+            parsed[2]
+        );
+        
+        // Structured comments also attach to commands inside of blocks.
+        match &parse("{ @(doc: 1)@ show; show }").unwrap()[0] {
+            ExpressionValue::Block(body, _) => {
+                assert_eq!(1, body[0].meta.len());
+                assert_eq!(0, body[1].meta.len());
+            },
+            other => panic!("expected a block, got {:?}", other),
+        }
+        
+        assert_eq!(Rule::Comment, parse("show\n@@ never closed").unwrap_err().expected);
+        assert_eq!(Rule::Comment, parse("@{ never closed }").unwrap_err().expected);
+        assert_eq!(Rule::StructComment, parse("@(doc 1)@ show").unwrap_err().expected);
+    }
+    
+    #[test]
+    fn test_parse_spans() {
+        use crate::parser::{parse_file, ExpressionValue, FileId};
+        
+        let source = "show\nlet f = [x: Int]{+ x 1}";
+        let parsed = parse_file(source, FileId(3)).unwrap();
+        let span_text = |value: &ExpressionValue| {
+            let span = value.span();
+            assert_eq!(FileId(3), span.file);
+            &source[span.start..span.end]
+        };
+        
+        assert_eq!("show", span_text(&parsed[0]));
+        assert_eq!("let f = [x: Int]{+ x 1}", span_text(&parsed[1]));
+        if let ExpressionValue::Expression(command) = &parsed[1] {
+            assert_eq!("f", span_text(&command.args[0].1));
+            assert_eq!("[x: Int]{+ x 1}", span_text(&command.args[1].1));
+            if let ExpressionValue::Procedure(args, body, _) = &command.args[1].1 {
+                assert_eq!("Int", span_text(&args[0].1));
+                assert_eq!("+ x 1", &source[body[0].span.start..body[0].span.end]);
+                assert_eq!("x", span_text(&body[0].args[0].1));
+            } else {
+                panic!("expected a procedure, got {:?}", command.args[1].1);
+            }
+        } else {
+            panic!("expected a command, got {:?}", parsed[1]);
+        }
+    }
+    
+    #[test]
+    fn test_parse_errors() {
+        use crate::parser::{parse, Rule};
+        
+        // Given: a command expression that is never closed
+        let err = parse("let x = (+ 1 1").unwrap_err();
+        // Then: the error points at the end of the line, inside the command expression
+        assert_eq!(Rule::CommandExpression, err.expected);
+        assert_eq!((1, 15), (err.line, err.column));
+        assert_eq!(14..14, err.span);
+        
+        // Given: a procedure whose argument list is malformed on the second line
+        let err = parse("show\nlet f = [x: Int 5]{}").unwrap_err();
+        // Then: the error is located on the offending token
+        assert_eq!(Rule::ArgumentList, err.expected);
+        assert_eq!((2, 17), (err.line, err.column));
+        assert_eq!(21..22, err.span);
+        assert_eq!("let f = [x: Int 5]{}\n                ^", err.snippet);
+        
+        // Given: a procedure without a body
+        let err = parse("let f = [x: Int] 5").unwrap_err();
+        assert_eq!(Rule::BlockExpression, err.expected);
+        assert_eq!(17..18, err.span);
+        
+        // Given: a command followed by garbage
+        let err = parse("let x = 1 $$").unwrap_err();
+        assert_eq!(Rule::EndOfCommand, err.expected);
+        assert_eq!(10..12, err.span);
+        assert_eq!("let x = 1 $$\n          ^^", err.snippet);
+        
+        // Given: an unterminated string
+        let err = parse("\"abc").unwrap_err();
+        assert_eq!(Rule::String, err.expected);
+    }
 }
-
- 
-
-
-
@@ -0,0 +1,290 @@
+use std::collections::HashSet;
+
+use crate::placemodel::storage::{PlaceStore, StoreError};
+use crate::placemodel::pathtypes::Link;
+use crate::primitive::types::{Place, PlaceId, Relation, RelationId};
+
+/// One context's view of a place store, e.g. an editing session's.
+///
+/// A context can freeze places, which makes them immutable to it: putting or deleting a frozen
+/// place through the context fails with `StoreError::Frozen`. Freezing is non-invasive -- the
+/// places themselves are unchanged, so the underlying store can still change them.
+///
+/// In a transaction, the places that are changed are only checked when it is committed, so a
+/// transaction that touches a frozen place is rolled back as a whole.
+pub struct Context<S: PlaceStore> {
+    store: S,
+    frozen: HashSet<PlaceId>,
+    /// The places the open transaction changes, or whose relations it changes.
+    changed: Option<HashSet<PlaceId>>,
+}
+
+impl<S: PlaceStore> Context<S> {
+    pub fn new(store: S) -> Context<S> {
+        Context { store, frozen: HashSet::new(), changed: None }
+    }
+
+    /// The underlying store, which is not affected by what the context froze.
+    pub fn store(&self) -> &S {
+        &self.store
+    }
+
+    pub fn into_store(self) -> S {
+        self.store
+    }
+
+    /// Freezes a place. Freezing is idempotent.
+    pub fn freeze(&mut self, id: &PlaceId) -> Result<(), StoreError> {
+        self.store.get_place(id).ok_or(StoreError::Missing(*id))?;
+        self.frozen.insert(*id);
+        Ok(())
+    }
+
+    /// Thaws a place, so that it can be changed again. Thawing is idempotent.
+    pub fn thaw(&mut self, id: &PlaceId) {
+        self.frozen.remove(id);
+    }
+
+    /// Freezes a place and everything it possesses, and everything they possess in turn. Returns
+    /// how many places were frozen.
+    pub fn freeze_tree(&mut self, id: &PlaceId) -> Result<usize, StoreError> {
+        let tree = self.tree(id)?;
+        let count = tree.len();
+        self.frozen.extend(tree);
+        Ok(count)
+    }
+
+    /// Thaws a place and everything it possesses, like `freeze_tree`.
+    pub fn thaw_tree(&mut self, id: &PlaceId) -> Result<usize, StoreError> {
+        let tree = self.tree(id)?;
+        for id in tree.iter() {
+            self.frozen.remove(id);
+        }
+        Ok(tree.len())
+    }
+
+    /// The frozen places, in no particular order.
+    pub fn frozen(&self) -> impl Iterator<Item = &PlaceId> {
+        self.frozen.iter()
+    }
+
+    /// Checks that a place can be changed now, or notes it to be checked when the open
+    /// transaction is committed.
+    fn check_change(&mut self, id: &PlaceId) -> Result<(), StoreError> {
+        match &mut self.changed {
+            Some(changed) => {
+                changed.insert(*id);
+                Ok(())
+            },
+            None => self.check_mutable(id),
+        }
+    }
+
+    /// A place and the places it possesses, directly or through its possessions.
+    fn tree(&self, root: &PlaceId) -> Result<HashSet<PlaceId>, StoreError> {
+        self.store.get_place(root).ok_or(StoreError::Missing(*root))?;
+        let mut tree = HashSet::new();
+        let mut pending = vec![*root];
+        while let Some(id) = pending.pop() {
+            if self.store.get_place(&id).is_some() && tree.insert(id) {
+                pending.extend(self.store.possessions_of(&id).iter().map(|link| *link.get_curr()));
+            }
+        }
+        Ok(tree)
+    }
+}
+
+impl<S: PlaceStore> PlaceStore for Context<S> {
+    fn put_place(&mut self, place: Place) -> Result<(), StoreError> {
+        self.check_change(&place.get_id())?;
+        self.store.put_place(place)
+    }
+
+    fn get_place(&self, id: &PlaceId) -> Option<&Place> {
+        self.store.get_place(id)
+    }
+
+    fn delete_place(&mut self, id: &PlaceId) -> Result<(), StoreError> {
+        self.check_change(id)?;
+        self.store.delete_place(id)?;
+        if self.changed.is_none() {
+            self.frozen.remove(id);
+        }
+        Ok(())
+    }
+
+    fn place_ids(&self) -> Vec<PlaceId> {
+        self.store.place_ids()
+    }
+
+    fn is_frozen(&self, id: &PlaceId) -> bool {
+        self.frozen.contains(id) || self.store.is_frozen(id)
+    }
+
+    fn links_from(&self, id: &PlaceId) -> Vec<Link> {
+        self.store.links_from(id)
+    }
+
+    fn links_to(&self, id: &PlaceId) -> Vec<Link> {
+        self.store.links_to(id)
+    }
+
+    fn root(&self) -> Option<PlaceId> {
+        self.store.root()
+    }
+
+    fn set_root(&mut self, id: &PlaceId) -> Result<(), StoreError> {
+        self.store.set_root(id)
+    }
+
+    fn begin(&mut self) -> Result<(), StoreError> {
+        self.store.begin()?;
+        self.changed = Some(HashSet::new());
+        Ok(())
+    }
+
+    /// Fails with `StoreError::Frozen`, and rolls the transaction back, if it changed a frozen
+    /// place.
+    fn commit(&mut self) -> Result<(), StoreError> {
+        let changed = self.changed.take().ok_or(StoreError::NoTransaction)?;
+        let mut changed: Vec<PlaceId> = changed.into_iter().collect();
+        changed.sort();
+        if let Some(frozen) = changed.iter().find(|id| self.is_frozen(id)) {
+            let frozen = *frozen;
+            self.store.rollback()?;
+            return Err(StoreError::Frozen(frozen));
+        }
+        self.store.commit()
+    }
+
+    fn rollback(&mut self) -> Result<(), StoreError> {
+        self.changed = None;
+        self.store.rollback()
+    }
+
+    fn in_transaction(&self) -> bool {
+        self.store.in_transaction()
+    }
+
+    /// A relation belongs to the place it is from, so it cannot be put while that place is
+    /// frozen.
+    fn put_relation(&mut self, relation: Relation) -> Result<(), StoreError> {
+        self.check_change(&relation.get_from())?;
+        if let Some(old) = self.store.get_relation(&relation.get_id()).map(|old| old.get_from()) {
+            self.check_change(&old)?;
+        }
+        self.store.put_relation(relation)
+    }
+
+    fn get_relation(&self, id: &RelationId) -> Option<&Relation> {
+        self.store.get_relation(id)
+    }
+
+    fn delete_relation(&mut self, id: &RelationId) -> Result<(), StoreError> {
+        let from = self.store.get_relation(id).ok_or(StoreError::MissingRelation(*id))?.get_from();
+        self.check_change(&from)?;
+        self.store.delete_relation(id)
+    }
+
+    fn relations_from(&self, id: &PlaceId, relation_type: &str) -> Vec<&Relation> {
+        self.store.relations_from(id, relation_type)
+    }
+
+    fn relations_to(&self, id: &PlaceId, relation_type: &str) -> Vec<&Relation> {
+        self.store.relations_to(id, relation_type)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::placemodel::context::Context;
+    use crate::placemodel::storage::{HashMapPlaceStore, PlaceStore, StoreError};
+    use crate::primitive::types::{Place, PlaceId, Relation, AttributeData};
+    use crate::model::PrimitiveData;
+
+    #[test]
+    fn frozen_places_cannot_change() {
+        // Given: a context with a place in it
+        let mut context = Context::new(HashMapPlaceStore::new());
+        let place = Place::generate_new();
+        let id = place.get_id();
+        context.put_place(place.clone()).unwrap();
+
+        // When: the place is frozen, twice
+        context.freeze(&id).unwrap();
+        context.freeze(&id).unwrap();
+
+        // Then: it cannot be put, have attributes put, be deleted, or have relations from it put
+        assert_eq!(Err(StoreError::Frozen(id)), context.put_place(place.clone()));
+        let one = AttributeData::Data(PrimitiveData::Int(1));
+        assert_eq!(Err(StoreError::Frozen(id)), context.put_attr(&id, "size".to_owned(), one.clone()));
+        assert_eq!(Err(StoreError::Frozen(id)), context.delete_place(&id));
+        assert!(context.get_place(&id).is_some());
+        let other = Place::generate_new();
+        let other_id = other.get_id();
+        context.put_place(other).unwrap();
+        let from_frozen = Relation::new("calls".to_owned(), id, other_id);
+        assert_eq!(Err(StoreError::Frozen(id)), context.put_relation(from_frozen.clone()));
+        context.put_relation(Relation::new("calls".to_owned(), other_id, id)).unwrap();
+
+        // When: it is thawed
+        // Then: it can be changed again
+        context.thaw(&id);
+        context.thaw(&id);
+        context.put_attr(&id, "size".to_owned(), one.clone()).unwrap();
+        context.put_relation(from_frozen).unwrap();
+        assert_eq!(Some(&one), context.get_place(&id).unwrap().get_attr(&"size".to_owned()));
+        let missing = Place::generate_id();
+        assert_eq!(Err(StoreError::Missing(missing)), context.freeze(&missing));
+    }
+
+    #[test]
+    fn freeze_whole_trees() {
+        // Given: a place that possesses a child, which refers back to it
+        let mut context = Context::new(HashMapPlaceStore::new());
+        let (root, child) = (Place::generate_new(), Place::generate_new());
+        let (root_id, child_id) = (root.get_id(), child.get_id());
+        context.put_place(root).unwrap();
+        context.put_linked_place(&root_id, "child".to_owned(), child).unwrap();
+        context.put_attr(&child_id, "parent".to_owned(), AttributeData::Reference(root_id)).unwrap();
+
+        // When: the root's tree is frozen
+        // Then: both are frozen, but only in the context
+        assert_eq!(Ok(2), context.freeze_tree(&root_id));
+        assert!(context.is_frozen(&child_id));
+        assert!(!context.store().is_frozen(&child_id));
+
+        // When: the child's tree is thawed
+        // Then: the root, which it only refers to, stays frozen
+        assert_eq!(Ok(1), context.thaw_tree(&child_id));
+        assert_eq!(vec![&root_id], context.frozen().collect::<Vec<&PlaceId>>());
+    }
+
+    #[test]
+    fn frozen_places_are_checked_at_commit() {
+        // Given: a context with a frozen place and a thawed one
+        let mut context = Context::new(HashMapPlaceStore::new());
+        let (frozen, thawed) = (Place::generate_new(), Place::generate_new());
+        let (frozen_id, thawed_id) = (frozen.get_id(), thawed.get_id());
+        context.put_place(frozen).unwrap();
+        context.put_place(thawed).unwrap();
+        context.freeze(&frozen_id).unwrap();
+
+        // When: a transaction changes both
+        let one = AttributeData::Data(PrimitiveData::Int(1));
+        context.begin().unwrap();
+        context.put_attr(&thawed_id, "size".to_owned(), one.clone()).unwrap();
+        context.put_attr(&frozen_id, "size".to_owned(), one.clone()).unwrap();
+
+        // Then: it fails to commit, and neither is changed
+        assert_eq!(Err(StoreError::Frozen(frozen_id)), context.commit());
+        assert!(!context.in_transaction());
+        assert!(context.get_place(&thawed_id).unwrap().get_attr(&"size".to_owned()).is_none());
+        assert!(context.get_place(&frozen_id).unwrap().get_attr(&"size".to_owned()).is_none());
+
+        // When: a transaction only changes the thawed place
+        // Then: it commits
+        context.transact(|context| context.put_attr(&thawed_id, "size".to_owned(), one.clone())).unwrap();
+        assert_eq!(Some(&one), context.get_place(&thawed_id).unwrap().get_attr(&"size".to_owned()));
+    }
+}
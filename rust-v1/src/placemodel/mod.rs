@@ -1,2 +1,3 @@
 pub mod storage;
 pub mod pathtypes;
+pub mod context;
@@ -5,24 +5,37 @@ use crate::primitive::types::PlaceId;
 /// The Link can be invalidated with respect to a certain place graph if:
 /// 1) The places are no longer valid for traversal (e.g. one of the two places was deleted from the place store)
 /// 2) The attribute relationship "chain" is broken (e.g. the attribute name between the two nodes has changed)
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Link {
     prev: PlaceId,
     curr: PlaceId,
     attr_name: String,
+    kind: RelationKind,
+}
+
+/// The two kinds of relations between places, from "The Possession Model" and "The Reference
+/// Model" in docs/concepts.md.
+#[derive(Debug, Clone, Copy, Hash, Ord, PartialOrd, Eq, PartialEq)]
+pub enum RelationKind {
+    /// The first place owns, and contains, the second. Each place has one owner, except the
+    /// root, which owns itself.
+    Possession,
+    /// The first place depends on the second. References can be many, and can be broken.
+    Reference,
 }
 
 impl Link {
-    pub fn new(prev: PlaceId, curr: PlaceId, attr_name: String) -> Option<Link> {
+    pub fn new(prev: PlaceId, curr: PlaceId, attr_name: String, kind: RelationKind) -> Option<Link> {
         if attr_name.is_empty() {
             return None;
         }
-        Some(Link{ prev, curr, attr_name })
+        Some(Link{ prev, curr, attr_name, kind })
     }
     
     pub fn get_prev(&self) -> &PlaceId { &self.prev }
     pub fn get_curr(&self) -> &PlaceId { &self.curr }
     pub fn get_attr_name(&self) -> &String { &self.attr_name }
+    pub fn get_kind(&self) -> RelationKind { self.kind }
 }
 
 /// A Path is a representation of many Links, forming a possibly-valid traversal between two Places that may be
@@ -1,35 +1,351 @@
 use crate::primitive::types::Place;
 use crate::primitive::types::PlaceId;
-use std::collections::HashMap;
+use crate::primitive::types::{Relation, RelationId};
+use std::collections::{HashMap, HashSet};
 use crate::placemodel::pathtypes::Path;
 use crate::primitive::types::AttributeData;
-use crate::placemodel::pathtypes::Link;
+use crate::placemodel::pathtypes::{Link, RelationKind};
+use crate::model::PrimitiveData;
+use std::fmt;
 
+/// Why a place store refused a change.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StoreError {
+    /// The place is frozen in the context the change was made in.
+    Frozen(PlaceId),
+    /// The place is not in the store.
+    Missing(PlaceId),
+    /// A link from the place was given no attribute name.
+    UnnamedLink(PlaceId),
+    /// The place already has an owner, and a place can only have one.
+    AlreadyOwned(PlaceId),
+    /// Possessing the place would make it own itself, directly or through its possessions.
+    OwnershipCycle(PlaceId),
+    /// The relation is not in the store.
+    MissingRelation(RelationId),
+    /// The place is referred to by a place that is not being deleted with it.
+    Referenced(PlaceId),
+    /// `begin` was called while a transaction was open.
+    TransactionOpen,
+    /// `commit` or `rollback` was called without a transaction.
+    NoTransaction,
+}
+
+impl fmt::Display for StoreError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StoreError::Frozen(id) => write!(f, "The place {} is frozen.", id),
+            StoreError::Missing(id) => write!(f, "The place {} is not in the place store.", id),
+            StoreError::UnnamedLink(id) => write!(f, "A link from the place {} needs an attribute name.", id),
+            StoreError::AlreadyOwned(id) => write!(f, "The place {} already has an owner.", id),
+            StoreError::OwnershipCycle(id) =>
+                write!(f, "The place {} cannot be possessed by a place it possesses.", id),
+            StoreError::MissingRelation(id) => write!(f, "The relation {} is not in the place store.", id),
+            StoreError::Referenced(id) => write!(f, "The place {} is still referred to by other places.", id),
+            StoreError::TransactionOpen => write!(f, "A transaction is already open."),
+            StoreError::NoTransaction => write!(f, "There is no open transaction."),
+        }
+    }
+}
+
+impl std::error::Error for StoreError {}
+
+/// What `delete_place_with` does with the possessions of a place and the references to it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeletePolicy {
+    /// Delete the place and everything it possesses. References to them are left broken.
+    Cascade,
+    /// Like `Cascade`, but refuse while a place that is not being deleted refers to any of them.
+    Refuse,
+    /// Delete only the place. What it possesses is left without an owner, and references to it
+    /// are left broken. Neither is marked on the places or links themselves: they are only
+    /// marked in the `orphans` and `broken_references` of the next `check_integrity` report.
+    Orphan,
+}
+
+/// What `check_integrity` found wrong with a store.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct IntegrityReport {
+    /// Possessions of places that are not in the store.
+    pub dangling_possessions: Vec<Link>,
+    /// References to places that are not in the store. References are allowed to break, so these
+    /// do not make the store inconsistent.
+    pub broken_references: Vec<Link>,
+    /// Places other than the root that have no owner. Without a root, there are no orphans.
+    pub orphans: Vec<PlaceId>,
+    /// Places with more than one owner, and places that possess themselves through their
+    /// possessions.
+    pub ownership_violations: Vec<StoreError>,
+}
+
+impl IntegrityReport {
+    /// Whether every possession leads to a place in the store, and possessions form a semilattice.
+    pub fn is_consistent(&self) -> bool {
+        self.dangling_possessions.is_empty() && self.ownership_violations.is_empty()
+    }
+}
+
+/// Stores places by id, and keeps the possessions between them a semilattice: every place has at
+/// most one owner, no place possesses itself through its possessions, and the root, if there is
+/// one, owns itself.
 pub trait PlaceStore {
-    fn put_place(&mut self, place: Place);
+    fn put_place(&mut self, place: Place) -> Result<(), StoreError>;
     fn get_place(&self, id: &PlaceId) -> Option<&Place>;
-    fn delete_place(&mut self, id: &PlaceId);
     
+    /// Deletes only the place itself. Links to it from other places are left dangling, so
+    /// prefer `delete_place_with`.
+    fn delete_place(&mut self, id: &PlaceId) -> Result<(), StoreError>;
+    
+    /// The ids of every place in the store, in order.
+    fn place_ids(&self) -> Vec<PlaceId>;
+    
+    /// The links out of a place through its attributes, ordered by attribute name.
+    fn links_from(&self, id: &PlaceId) -> Vec<Link>;
+    
+    /// The links into a place from the attributes of places in the store, ordered by the place
+    /// they come from and then by attribute name. Links into a place that was deleted are kept
+    /// until the places they come from are changed.
+    fn links_to(&self, id: &PlaceId) -> Vec<Link>;
+    
+    /// Starts a transaction. The changes made until `commit` can be undone with `rollback`, and
+    /// only need to keep the store consistent by the time they are committed.
+    fn begin(&mut self) -> Result<(), StoreError>;
+    
+    /// Checks the changes made since `begin`, and keeps them if the store is consistent.
+    /// Otherwise they are rolled back, and the error is returned.
+    fn commit(&mut self) -> Result<(), StoreError>;
+    
+    /// Undoes the changes made since `begin`.
+    fn rollback(&mut self) -> Result<(), StoreError>;
+    
+    fn in_transaction(&self) -> bool;
+    
+    /// Makes the changes of `edit` in a transaction, which is committed if `edit` succeeds and
+    /// rolled back if it fails. Inside another transaction, `edit` is part of that one instead.
+    fn transact<T, F>(&mut self, edit: F) -> Result<T, StoreError>
+        where Self: Sized, F: FnOnce(&mut Self) -> Result<T, StoreError> {
+        let opened = self.open_transaction()?;
+        let edited = edit(self);
+        self.close_transaction(opened, edited.as_ref().map(|_| ()).map_err(|err| *err))?;
+        edited
+    }
+    
+    /// Begins a transaction unless one is open, and returns whether it did. See `transact`.
+    fn open_transaction(&mut self) -> Result<bool, StoreError> {
+        if self.in_transaction() {
+            return Ok(false);
+        }
+        self.begin()?;
+        Ok(true)
+    }
+    
+    /// Ends a transaction from `open_transaction`, committing it if the edits in it succeeded
+    /// and rolling it back if they failed. A transaction it did not open is left to its owner.
+    fn close_transaction(&mut self, opened: bool, edited: Result<(), StoreError>) -> Result<(), StoreError> {
+        if !opened {
+            return edited;
+        }
+        match edited {
+            Ok(()) => self.commit(),
+            Err(err) => {
+                self.rollback()?;
+                Err(err)
+            },
+        }
+    }
+    
+    /// The place that owns itself, if there is one.
+    fn root(&self) -> Option<PlaceId>;
     
-    fn put_linked_place(&mut self, from: &PlaceId, attr: String, mut place: Place) -> Option<Link> {
-        if let Some(from_place) = self.get_place(from) {
-            let mut modified_from_place = from_place.clone();
-            modified_from_place.put_attr(attr.clone(), AttributeData::Place(place.get_id()));
-            let result = Some(Link::new(from_place.get_id(), place.get_id(), attr).unwrap());
-            self.put_place(modified_from_place);
-            self.put_place(place);
-            result
+    /// Makes a place in the store the root. It must not be possessed by another place.
+    fn set_root(&mut self, id: &PlaceId) -> Result<(), StoreError>;
+    
+    /// The place that possesses a place. The root is its own owner.
+    fn owner_of(&self, id: &PlaceId) -> Option<PlaceId> {
+        if self.root() == Some(*id) {
+            return Some(*id);
+        }
+        self.links_to(id).into_iter()
+            .find(|link| link.get_kind() == RelationKind::Possession)
+            .map(|link| *link.get_prev())
+    }
+    
+    /// Checks that a place has at most one owner, or none if it is the root, and that it does not
+    /// possess itself through its possessions.
+    fn check_ownership(&self, id: &PlaceId) -> Result<(), StoreError> {
+        let owners = self.links_to(id).iter()
+            .filter(|link| link.get_kind() == RelationKind::Possession)
+            .count();
+        let root = self.root();
+        if owners > 1 || (owners > 0 && root == Some(*id)) {
+            return Err(StoreError::AlreadyOwned(*id));
+        }
+        // Follow the owners up until they reach the root, leave the store, or come back.
+        let mut seen = HashSet::new();
+        let mut owner = if root == Some(*id) { None } else { self.owner_of(id) };
+        while let Some(current) = owner {
+            if current == *id {
+                return Err(StoreError::OwnershipCycle(*id));
+            }
+            if !seen.insert(current) || root == Some(current) {
+                break;
+            }
+            owner = self.owner_of(&current);
+        }
+        Ok(())
+    }
+    
+    /// The links to the places a place possesses, ordered by attribute name.
+    fn possessions_of(&self, id: &PlaceId) -> Vec<Link> {
+        self.links_from(id).into_iter().filter(|link| link.get_kind() == RelationKind::Possession).collect()
+    }
+    
+    /// The links to the places a place refers to, ordered by attribute name.
+    fn references_of(&self, id: &PlaceId) -> Vec<Link> {
+        self.links_from(id).into_iter().filter(|link| link.get_kind() == RelationKind::Reference).collect()
+    }
+    
+    /// Puts a relation between two places in the store, replacing the one with the same id.
+    fn put_relation(&mut self, relation: Relation) -> Result<(), StoreError>;
+    fn get_relation(&self, id: &RelationId) -> Option<&Relation>;
+    fn delete_relation(&mut self, id: &RelationId) -> Result<(), StoreError>;
+    
+    /// The relations of a type from a place, ordered by id.
+    fn relations_from(&self, id: &PlaceId, relation_type: &str) -> Vec<&Relation>;
+    
+    /// The relations of a type to a place, ordered by id.
+    fn relations_to(&self, id: &PlaceId, relation_type: &str) -> Vec<&Relation>;
+    
+    /// The places that deleting a place with a policy would delete, starting with the place.
+    /// Fails, without changing anything, if the deletion cannot be made: if any of the places
+    /// or the owner of the place is frozen, or if the policy refuses a reference.
+    fn places_to_delete(&self, id: &PlaceId, policy: DeletePolicy) -> Result<Vec<PlaceId>, StoreError> {
+        self.get_place(id).ok_or(StoreError::Missing(*id))?;
+        let mut doomed = vec![*id];
+        let mut seen: HashSet<PlaceId> = doomed.iter().cloned().collect();
+        let mut next = 0;
+        while policy != DeletePolicy::Orphan && next < doomed.len() {
+            for link in self.possessions_of(&doomed[next]) {
+                if self.get_place(link.get_curr()).is_some() && seen.insert(*link.get_curr()) {
+                    doomed.push(*link.get_curr());
+                }
+            }
+            next += 1;
+        }
+        for place in doomed.iter() {
+            self.check_mutable(place)?;
+        }
+        if let Some(owner) = self.owner_of(id) {
+            self.check_mutable(&owner)?;
+        }
+        if policy == DeletePolicy::Refuse {
+            for place in doomed.iter() {
+                let outside = self.links_to(place).iter()
+                    .any(|link| link.get_kind() == RelationKind::Reference && !seen.contains(link.get_prev()));
+                if outside {
+                    return Err(StoreError::Referenced(*place));
+                }
+            }
+        }
+        Ok(doomed)
+    }
+    
+    /// Deletes a place, and unlinks it from its owner, so that no possession is left dangling.
+    /// What happens to its possessions and the references to it depends on the policy. Returns
+    /// the places that were deleted.
+    fn delete_place_with(
+        &mut self,
+        id: &PlaceId,
+        policy: DeletePolicy) -> Result<Vec<PlaceId>, StoreError> {
+        let doomed = self.places_to_delete(id, policy)?;
+        if let Some(owner) = self.owner_of(id).filter(|owner| owner != id) {
+            let mut owner_place = self.get_place(&owner).cloned().ok_or(StoreError::Missing(owner))?;
+            for link in self.possessions_of(&owner).iter().filter(|link| link.get_curr() == id) {
+                owner_place.remove_attr(link.get_attr_name());
+            }
+            self.put_place(owner_place)?;
+        }
+        for place in doomed.iter() {
+            self.delete_place(place)?;
+        }
+        Ok(doomed)
+    }
+    
+    /// Finds dangling possessions, broken references, orphans and ownership violations, e.g. to
+    /// repair a store that places were deleted from with `delete_place`.
+    fn check_integrity(&self) -> IntegrityReport {
+        let mut report = IntegrityReport::default();
+        let root = self.root();
+        for id in self.place_ids() {
+            for link in self.links_from(&id) {
+                if self.get_place(link.get_curr()).is_none() {
+                    match link.get_kind() {
+                        RelationKind::Possession => report.dangling_possessions.push(link),
+                        RelationKind::Reference => report.broken_references.push(link),
+                    }
+                }
+            }
+            if let Err(violation) = self.check_ownership(&id) {
+                report.ownership_violations.push(violation);
+            } else if root.is_some() && self.owner_of(&id).is_none() {
+                report.orphans.push(id);
+            }
+        }
+        report
+    }
+    
+    /// Idempotent put of a single attribute of a relation in the store.
+    fn put_relation_attr(&mut self, id: &RelationId, key: String, value: PrimitiveData) -> Result<(), StoreError> {
+        let mut relation = self.get_relation(id).cloned().ok_or(StoreError::MissingRelation(*id))?;
+        relation.put_attr(key, value);
+        self.put_relation(relation)
+    }
+    
+    /// Whether the place is frozen, so that it cannot be changed or deleted through this store.
+    fn is_frozen(&self, _id: &PlaceId) -> bool {
+        false
+    }
+    
+    /// Fails if the place is frozen, e.g. to check every place an edit touches before making it.
+    fn check_mutable(&self, id: &PlaceId) -> Result<(), StoreError> {
+        if self.is_frozen(id) {
+            Err(StoreError::Frozen(*id))
         } else {
-            None
+            Ok(())
         }
     }
     
+    /// Idempotent put of a single attribute of a place in the store.
+    fn put_attr(&mut self, id: &PlaceId, key: String, value: AttributeData) -> Result<(), StoreError> {
+        let mut place = self.get_place(id).cloned().ok_or(StoreError::Missing(*id))?;
+        place.put_attr(key, value);
+        self.put_place(place)
+    }
+    
+    /// Puts a place and links to it from another as a possession, in one transaction. Inside
+    /// another transaction, a failure leaves that one as it was.
+    fn put_linked_place(&mut self, from: &PlaceId, attr: String, place: Place) -> Result<Link, StoreError> {
+        let from_place = self.get_place(from).ok_or(StoreError::Missing(*from))?.clone();
+        let mut modified_from_place = from_place.clone();
+        modified_from_place.put_attr(attr.clone(), AttributeData::Place(place.get_id()));
+        let result = Link::new(from_place.get_id(), place.get_id(), attr, RelationKind::Possession)
+            .ok_or(StoreError::UnnamedLink(*from))?;
+        let opened = self.open_transaction()?;
+        let put = self.put_place(modified_from_place).and_then(|_| match self.put_place(place) {
+            // The transaction is not ours to roll back, so take back the link by hand.
+            Err(err) if !opened => self.put_place(from_place).and(Err(err)),
+            put => put,
+        });
+        self.close_transaction(opened, put)?;
+        Ok(result)
+    }
+    
     fn verify_path(&self, root: &PlaceId, path: &Path) -> bool {
         // Start traversal from the root.
-        let mut expected_place_id= root;
+        let mut expected_place_id = *root;
         // Verify that the first item in the path is indeed the root.
         if let Some((first_place_id, first_attr_name)) = path.get_traversal_list().first() {
-            if first_place_id != expected_place_id {
+            if *first_place_id != expected_place_id {
                 return false;
             }
         }
@@ -37,11 +353,11 @@ pub trait PlaceStore {
         let mut iter = path.get_traversal_list().iter().skip(1);
         while let Some((curr_place_id, attr_name)) = iter.next() {
             // If the next place is not in the store, fail.
-            if let Some(curr_place) = self.get_place(expected_place_id) {
+            if let Some(curr_place) = self.get_place(&expected_place_id) {
                 // If the attribute doesn't exist, fail.
                 if let Some(attr_data) = curr_place.get_attr(attr_name) {
                     // If the attribute is of the wrong type, fail.
-                    if let AttributeData::Place(next_place_id) = attr_data {
+                    if let Some(next_place_id) = attr_data.linked_place() {
                         // Otherwise, we have found the next place in the correct attribute spot on the current place.
                         // Continue (if we are at the last one, this is an unnecessary write).
                         expected_place_id = next_place_id;
@@ -62,36 +378,396 @@ pub trait PlaceStore {
 
 #[derive(Debug)]
 pub struct HashMapPlaceStore {
-    store: HashMap<PlaceId, Place>
+    store: HashMap<PlaceId, Place>,
+    /// For every place that is linked to, the places that link to it, through which attributes
+    /// and how. Kept up to date as places are put and deleted.
+    links_to: HashMap<PlaceId, HashSet<(PlaceId, String, RelationKind)>>,
+    root: Option<PlaceId>,
+    relations: HashMap<RelationId, Relation>,
+    /// For every place, the relations of any type that it is the `from` or `to` of.
+    relations_from: HashMap<PlaceId, HashSet<RelationId>>,
+    relations_to: HashMap<PlaceId, HashSet<RelationId>>,
+    /// How to undo the changes of the open transaction, oldest first.
+    journal: Option<Vec<Undo>>,
+}
+
+/// Undoes one change made in a transaction by putting back what was there before it.
+#[derive(Debug)]
+enum Undo {
+    Place(PlaceId, Option<Place>),
+    Relation(RelationId, Option<Relation>),
+    Root(Option<PlaceId>),
 }
 
 impl HashMapPlaceStore {
     pub fn new() -> HashMapPlaceStore {
-        HashMapPlaceStore { store: HashMap::new() }
+        HashMapPlaceStore {
+            store: HashMap::new(),
+            links_to: HashMap::new(),
+            root: None,
+            relations: HashMap::new(),
+            relations_from: HashMap::new(),
+            relations_to: HashMap::new(),
+            journal: None,
+        }
+    }
+    
+    /// Replaces or removes a place, keeping the link index up to date, and returns what was
+    /// there before. Nothing is checked.
+    fn replace_place(&mut self, id: PlaceId, place: Option<Place>) -> Option<Place> {
+        let old = self.store.remove(&id);
+        if let Some(old) = &old {
+            self.index_links(old, false);
+        }
+        if let Some(place) = place {
+            self.index_links(&place, true);
+            self.store.insert(id, place);
+        }
+        old
+    }
+    
+    /// Replaces or removes a relation, like `replace_place`.
+    fn replace_relation(&mut self, id: RelationId, relation: Option<Relation>) -> Option<Relation> {
+        let old = self.relations.remove(&id);
+        if let Some(old) = &old {
+            let ends = vec![(&mut self.relations_from, old.get_from()), (&mut self.relations_to, old.get_to())];
+            for (index, end) in ends {
+                if let Some(ids) = index.get_mut(&end) {
+                    ids.remove(&id);
+                    if ids.is_empty() {
+                        index.remove(&end);
+                    }
+                }
+            }
+        }
+        if let Some(relation) = relation {
+            self.relations_from.entry(relation.get_from()).or_default().insert(id);
+            self.relations_to.entry(relation.get_to()).or_default().insert(id);
+            self.relations.insert(id, relation);
+        }
+        old
+    }
+    
+    fn record(&mut self, undo: Undo) {
+        if let Some(journal) = &mut self.journal {
+            journal.push(undo);
+        }
+    }
+    
+    /// Checks the places a transaction changed, once all of its changes are made: what they
+    /// possess must be in the store and have one owner, and places that were deleted must not
+    /// be possessed anymore.
+    fn check_changes(&self, changed: &[PlaceId]) -> Result<(), StoreError> {
+        for id in changed.iter() {
+            if self.store.contains_key(id) {
+                self.check_ownership(id)?;
+                for link in self.possessions_of(id) {
+                    self.store.get(link.get_curr()).ok_or(StoreError::Missing(*link.get_curr()))?;
+                    self.check_ownership(link.get_curr())?;
+                }
+            } else if self.links_to(id).iter().any(|link| link.get_kind() == RelationKind::Possession) {
+                return Err(StoreError::Missing(*id));
+            }
+        }
+        Ok(())
+    }
+    
+    fn undo(&mut self, journal: Vec<Undo>) {
+        for undo in journal.into_iter().rev() {
+            match undo {
+                Undo::Place(id, old) => { self.replace_place(id, old); },
+                Undo::Relation(id, old) => { self.replace_relation(id, old); },
+                Undo::Root(old) => self.root = old,
+            }
+        }
+    }
+    
+    /// The relations of a type in one of the relation indices, ordered by id.
+    fn relations_in(
+        &self,
+        index: &HashMap<PlaceId, HashSet<RelationId>>,
+        id: &PlaceId,
+        relation_type: &str) -> Vec<&Relation> {
+        let mut relations: Vec<&Relation> = index.get(id).into_iter()
+            .flat_map(|ids| ids.iter())
+            .filter_map(|id| self.relations.get(id))
+            .filter(|relation| relation.get_type() == relation_type)
+            .collect();
+        relations.sort_by_key(|relation| relation.get_id());
+        relations
+    }
+    
+    /// Checks that the possessions of a place that is about to be put outside a transaction keep
+    /// every place to one owner and without cycles.
+    fn check_possessions(&self, place: &Place) -> Result<(), StoreError> {
+        let id = place.get_id();
+        let mut possessed = HashSet::new();
+        for link in outgoing_links(place).iter().filter(|link| link.get_kind() == RelationKind::Possession) {
+            let target = *link.get_curr();
+            let owned_elsewhere = self.root == Some(target) || self.links_to(&target).iter()
+                .any(|link| link.get_kind() == RelationKind::Possession && *link.get_prev() != id);
+            if owned_elsewhere || !possessed.insert(target) {
+                return Err(StoreError::AlreadyOwned(target));
+            }
+        }
+        // The place cannot possess itself, or any place that possesses it.
+        let mut ancestor = Some(id);
+        let mut seen = HashSet::new();
+        while let Some(current) = ancestor {
+            if possessed.contains(&current) {
+                return Err(StoreError::OwnershipCycle(current));
+            }
+            if !seen.insert(current) || self.root == Some(current) {
+                break;
+            }
+            ancestor = self.owner_of(&current);
+        }
+        Ok(())
+    }
+    
+    /// Adds the links out of a place to the reverse index, or removes them from it.
+    fn index_links(&mut self, place: &Place, add: bool) {
+        for link in outgoing_links(place) {
+            let from = (*link.get_prev(), link.get_attr_name().clone(), link.get_kind());
+            if add {
+                self.links_to.entry(*link.get_curr()).or_default().insert(from);
+            } else if let Some(links) = self.links_to.get_mut(link.get_curr()) {
+                links.remove(&from);
+                if links.is_empty() {
+                    self.links_to.remove(link.get_curr());
+                }
+            }
+        }
     }
 }
 
+/// The links out of a place, ordered by attribute name.
+pub fn outgoing_links(place: &Place) -> Vec<Link> {
+    let mut links: Vec<Link> = place.attributes()
+        .filter_map(|(name, data)| match data {
+            AttributeData::Place(to) => Link::new(place.get_id(), *to, name.clone(), RelationKind::Possession),
+            AttributeData::Reference(to) => Link::new(place.get_id(), *to, name.clone(), RelationKind::Reference),
+            AttributeData::Data(_) => None,
+        })
+        .collect();
+    links.sort_by(|a, b| a.get_attr_name().cmp(b.get_attr_name()));
+    links
+}
+
 impl PlaceStore for HashMapPlaceStore {
     
-    fn put_place(&mut self, place: Place) {
-        self.store.insert(place.get_id(), place);
+    fn put_place(&mut self, place: Place) -> Result<(), StoreError> {
+        if self.journal.is_none() {
+            self.check_possessions(&place)?;
+        }
+        let id = place.get_id();
+        let old = self.replace_place(id, Some(place));
+        self.record(Undo::Place(id, old));
+        Ok(())
     }
     
     fn get_place(&self, id: &PlaceId) -> Option<&Place> {
         self.store.get(id)
     }
     
-    fn delete_place(&mut self, id: &PlaceId) {
-        self.store.remove(id);
+    fn place_ids(&self) -> Vec<PlaceId> {
+        let mut ids: Vec<PlaceId> = self.store.keys().cloned().collect();
+        ids.sort();
+        ids
+    }
+    
+    /// Deleting a place deletes the relations from and to it too.
+    fn delete_place(&mut self, id: &PlaceId) -> Result<(), StoreError> {
+        let old = self.replace_place(*id, None);
+        self.record(Undo::Place(*id, old));
+        if self.root == Some(*id) {
+            self.record(Undo::Root(self.root));
+            self.root = None;
+        }
+        let relations: Vec<RelationId> = self.relations_from.get(id).into_iter()
+            .chain(self.relations_to.get(id))
+            .flat_map(|ids| ids.iter().cloned())
+            .collect();
+        for relation in relations.iter() {
+            self.delete_relation(relation)?;
+        }
+        Ok(())
+    }
+    
+    fn links_from(&self, id: &PlaceId) -> Vec<Link> {
+        self.store.get(id).map_or(vec![], outgoing_links)
+    }
+    
+    fn links_to(&self, id: &PlaceId) -> Vec<Link> {
+        let mut links: Vec<Link> = self.links_to.get(id).into_iter()
+            .flat_map(|links| links.iter())
+            .filter_map(|(from, name, kind)| Link::new(*from, *id, name.clone(), *kind))
+            .collect();
+        links.sort_by(|a, b| (a.get_prev(), a.get_attr_name()).cmp(&(b.get_prev(), b.get_attr_name())));
+        links
+    }
+    
+    fn begin(&mut self) -> Result<(), StoreError> {
+        if self.journal.is_some() {
+            return Err(StoreError::TransactionOpen);
+        }
+        self.journal = Some(vec![]);
+        Ok(())
+    }
+    
+    fn commit(&mut self) -> Result<(), StoreError> {
+        let journal = self.journal.take().ok_or(StoreError::NoTransaction)?;
+        let mut changed: Vec<PlaceId> = journal.iter()
+            .filter_map(|undo| match undo {
+                Undo::Place(id, _) => Some(*id),
+                _ => None,
+            })
+            .collect();
+        changed.sort();
+        changed.dedup();
+        if let Err(err) = self.check_changes(&changed) {
+            self.undo(journal);
+            return Err(err);
+        }
+        Ok(())
+    }
+    
+    fn rollback(&mut self) -> Result<(), StoreError> {
+        let journal = self.journal.take().ok_or(StoreError::NoTransaction)?;
+        self.undo(journal);
+        Ok(())
+    }
+    
+    fn in_transaction(&self) -> bool {
+        self.journal.is_some()
+    }
+    
+    fn root(&self) -> Option<PlaceId> {
+        self.root
+    }
+    
+    fn set_root(&mut self, id: &PlaceId) -> Result<(), StoreError> {
+        self.store.get(id).ok_or(StoreError::Missing(*id))?;
+        if self.links_to(id).iter().any(|link| link.get_kind() == RelationKind::Possession) {
+            return Err(StoreError::AlreadyOwned(*id));
+        }
+        self.record(Undo::Root(self.root));
+        self.root = Some(*id);
+        Ok(())
+    }
+    
+    fn put_relation(&mut self, relation: Relation) -> Result<(), StoreError> {
+        for end in [relation.get_from(), relation.get_to()].iter() {
+            self.store.get(end).ok_or(StoreError::Missing(*end))?;
+        }
+        let id = relation.get_id();
+        let old = self.replace_relation(id, Some(relation));
+        self.record(Undo::Relation(id, old));
+        Ok(())
+    }
+    
+    fn get_relation(&self, id: &RelationId) -> Option<&Relation> {
+        self.relations.get(id)
+    }
+    
+    fn delete_relation(&mut self, id: &RelationId) -> Result<(), StoreError> {
+        let old = self.replace_relation(*id, None).ok_or(StoreError::MissingRelation(*id))?;
+        self.record(Undo::Relation(*id, Some(old)));
+        Ok(())
+    }
+    
+    fn relations_from(&self, id: &PlaceId, relation_type: &str) -> Vec<&Relation> {
+        self.relations_in(&self.relations_from, id, relation_type)
+    }
+    
+    fn relations_to(&self, id: &PlaceId, relation_type: &str) -> Vec<&Relation> {
+        self.relations_in(&self.relations_to, id, relation_type)
+    }
+}
+
+/// Lets a boxed store, like the VM's, be wrapped in a `Context`.
+impl<S: PlaceStore + ?Sized> PlaceStore for Box<S> {
+    fn put_place(&mut self, place: Place) -> Result<(), StoreError> {
+        (**self).put_place(place)
+    }
+    
+    fn get_place(&self, id: &PlaceId) -> Option<&Place> {
+        (**self).get_place(id)
+    }
+    
+    fn delete_place(&mut self, id: &PlaceId) -> Result<(), StoreError> {
+        (**self).delete_place(id)
+    }
+    
+    fn place_ids(&self) -> Vec<PlaceId> {
+        (**self).place_ids()
+    }
+    
+    fn links_from(&self, id: &PlaceId) -> Vec<Link> {
+        (**self).links_from(id)
+    }
+    
+    fn links_to(&self, id: &PlaceId) -> Vec<Link> {
+        (**self).links_to(id)
+    }
+    
+    fn root(&self) -> Option<PlaceId> {
+        (**self).root()
+    }
+    
+    fn set_root(&mut self, id: &PlaceId) -> Result<(), StoreError> {
+        (**self).set_root(id)
+    }
+    
+    fn begin(&mut self) -> Result<(), StoreError> {
+        (**self).begin()
+    }
+    
+    fn commit(&mut self) -> Result<(), StoreError> {
+        (**self).commit()
+    }
+    
+    fn rollback(&mut self) -> Result<(), StoreError> {
+        (**self).rollback()
+    }
+    
+    fn in_transaction(&self) -> bool {
+        (**self).in_transaction()
+    }
+    
+    fn put_relation(&mut self, relation: Relation) -> Result<(), StoreError> {
+        (**self).put_relation(relation)
+    }
+    
+    fn get_relation(&self, id: &RelationId) -> Option<&Relation> {
+        (**self).get_relation(id)
+    }
+    
+    fn delete_relation(&mut self, id: &RelationId) -> Result<(), StoreError> {
+        (**self).delete_relation(id)
+    }
+    
+    fn relations_from(&self, id: &PlaceId, relation_type: &str) -> Vec<&Relation> {
+        (**self).relations_from(id, relation_type)
+    }
+    
+    fn relations_to(&self, id: &PlaceId, relation_type: &str) -> Vec<&Relation> {
+        (**self).relations_to(id, relation_type)
+    }
+    
+    fn is_frozen(&self, id: &PlaceId) -> bool {
+        (**self).is_frozen(id)
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::primitive::types::{Place, AttributeData};
-    use std::collections::HashMap;
-    use crate::placemodel::storage::{HashMapPlaceStore, PlaceStore};
-    use crate::placemodel::pathtypes::{Link, Path};
+    use crate::primitive::types::{Place, PlaceId, Relation, AttributeData};
+    use std::collections::{HashMap, HashSet};
+    use crate::placemodel::storage::{HashMapPlaceStore, PlaceStore, StoreError};
+    use crate::placemodel::storage::{DeletePolicy, IntegrityReport};
+    use crate::placemodel::pathtypes::{Link, Path, RelationKind};
+    use crate::model::PrimitiveData;
    
     #[test]
     fn verify_valid_path() {
@@ -108,7 +784,7 @@ mod tests {
   
         // Given: we put places into the store with links between them
         let mut valid_path = Path::with_root(place1.get_id());
-        store.put_place(place1.clone());
+        store.put_place(place1.clone()).unwrap();
         println!("state: {:#?}", store);
         valid_path.push_link(store.put_linked_place(&place1.get_id(), "foo".to_string(), place2.clone()).unwrap());
         println!("state: {:#?}", store);
@@ -124,10 +800,293 @@ mod tests {
         
         // Given: an invalid path
         let mut invalid_path = Path::with_root(place3.get_id());
-        invalid_path.push_link(Link::new(place3.get_id(), place1.get_id(), "meow".to_string()).unwrap());
+        invalid_path.push_link(Link::new(place3.get_id(), place1.get_id(), "meow".to_string(), RelationKind::Possession).unwrap());
         
         // When: we try to verify an invalid path through the attribute chain
         // Then: verification should fail
         assert_eq!(false, store.verify_path(&place3.get_id(), &invalid_path));
+        
+        // When: we try to link a place without an attribute name
+        // Then: it is refused, and the place is not put
+        let unnamed = Place::generate_new();
+        let unnamed_id = unnamed.get_id();
+        assert_eq!(
+            Err(StoreError::UnnamedLink(place3.get_id())),
+            store.put_linked_place(&place3.get_id(), String::new(), unnamed));
+        assert!(store.get_place(&unnamed_id).is_none());
     }
-}
\ No newline at end of file
+    
+    #[test]
+    fn links_are_indexed_both_ways() {
+        // Given: a place that possesses another, and one that refers to it twice
+        let mut store = HashMapPlaceStore::new();
+        let (mut a, mut b, c) = (Place::generate_new(), Place::generate_new(), Place::generate_new());
+        a.put_attr("child".to_string(), AttributeData::Place(c.get_id()));
+        a.put_attr("size".to_string(), AttributeData::Data(PrimitiveData::Int(1)));
+        b.put_attr("ref".to_string(), AttributeData::Reference(c.get_id()));
+        b.put_attr("alias".to_string(), AttributeData::Reference(c.get_id()));
+        for place in vec![a.clone(), b.clone(), c.clone()] {
+            store.put_place(place).unwrap();
+        }
+        let possession = |from: &Place, name: &str|
+            Link::new(from.get_id(), c.get_id(), name.to_string(), RelationKind::Possession).unwrap();
+        let link = |from: &Place, name: &str|
+            Link::new(from.get_id(), c.get_id(), name.to_string(), RelationKind::Reference).unwrap();
+        
+        // Then: the links can be found from either end
+        assert_eq!(vec![possession(&a, "child")], store.links_from(&a.get_id()));
+        assert_eq!(vec![link(&b, "alias"), link(&b, "ref")], store.links_from(&b.get_id()));
+        let mut expected = vec![possession(&a, "child"), link(&b, "alias"), link(&b, "ref")];
+        expected.sort_by(|x, y| (x.get_prev(), x.get_attr_name()).cmp(&(y.get_prev(), y.get_attr_name())));
+        assert_eq!(expected, store.links_to(&c.get_id()));
+        
+        // When: an attribute is rewritten to data, and another one is removed
+        // Then: their links are gone
+        store.put_attr(&b.get_id(), "ref".to_string(), AttributeData::Data(PrimitiveData::Int(2))).unwrap();
+        a.remove_attr(&"child".to_string());
+        store.put_place(a.clone()).unwrap();
+        assert_eq!(vec![link(&b, "alias")], store.links_to(&c.get_id()));
+        assert!(store.links_from(&a.get_id()).is_empty());
+        
+        // When: the place a link comes from is deleted
+        // Then: so is the link
+        store.delete_place(&b.get_id()).unwrap();
+        assert!(store.links_to(&c.get_id()).is_empty());
+        assert!(store.links_from(&b.get_id()).is_empty());
+    }
+    
+    #[test]
+    fn possessions_form_a_semilattice() {
+        // Given: a root that possesses a place, which possesses another and refers to a third
+        let mut store = HashMapPlaceStore::new();
+        let (root, mut a) = (Place::generate_new(), Place::generate_new());
+        let (b, c) = (Place::generate_new(), Place::generate_new());
+        let (root_id, a_id, b_id, c_id) = (root.get_id(), a.get_id(), b.get_id(), c.get_id());
+        store.put_place(root).unwrap();
+        store.set_root(&root_id).unwrap();
+        a.put_attr("child".to_string(), AttributeData::Place(b_id));
+        a.put_attr("dep".to_string(), AttributeData::Reference(c_id));
+        for place in vec![a.clone(), b.clone(), c.clone()] {
+            store.put_place(place).unwrap();
+        }
+        store.put_attr(&root_id, "a".to_string(), AttributeData::Place(a_id)).unwrap();
+        
+        // Then: every place knows its owner, and the root owns itself
+        assert_eq!(Some(root_id), store.owner_of(&root_id));
+        assert_eq!(Some(root_id), store.owner_of(&a_id));
+        assert_eq!(Some(a_id), store.owner_of(&b_id));
+        assert_eq!(None, store.owner_of(&c_id));
+        let targets = |links: Vec<Link>| links.iter().map(|link| *link.get_curr()).collect::<Vec<_>>();
+        assert_eq!(vec![b_id], targets(store.possessions_of(&a_id)));
+        assert_eq!(vec![c_id], targets(store.references_of(&a_id)));
+        
+        // Then: a place cannot get a second owner, or possess the root
+        assert_eq!(Err(StoreError::AlreadyOwned(b_id)),
+                   store.put_attr(&c_id, "b".to_string(), AttributeData::Place(b_id)));
+        assert_eq!(Err(StoreError::AlreadyOwned(root_id)),
+                   store.put_attr(&c_id, "root".to_string(), AttributeData::Place(root_id)));
+        let mut twice = c.clone();
+        twice.put_attr("x".to_string(), AttributeData::Place(a_id));
+        twice.put_attr("y".to_string(), AttributeData::Place(a_id));
+        assert_eq!(Err(StoreError::AlreadyOwned(a_id)), store.put_place(twice));
+        
+        // Then: a place cannot possess itself, or the places that possess it
+        assert_eq!(Err(StoreError::OwnershipCycle(c_id)),
+                   store.put_attr(&c_id, "me".to_string(), AttributeData::Place(c_id)));
+        let d = Place::generate_new();
+        let d_id = d.get_id();
+        store.put_place(d).unwrap();
+        store.put_attr(&c_id, "d".to_string(), AttributeData::Place(d_id)).unwrap();
+        assert_eq!(Err(StoreError::OwnershipCycle(c_id)),
+                   store.put_attr(&d_id, "c".to_string(), AttributeData::Place(c_id)));
+        store.put_attr(&b_id, "c".to_string(), AttributeData::Place(c_id)).unwrap();
+        
+        // Then: references can be many, and can make cycles
+        store.put_attr(&c_id, "a".to_string(), AttributeData::Reference(a_id)).unwrap();
+        store.put_attr(&b_id, "root".to_string(), AttributeData::Reference(root_id)).unwrap();
+        
+        // When: a possession is rewritten to a reference
+        // Then: the place can be possessed by another place
+        store.put_attr(&b_id, "c".to_string(), AttributeData::Reference(c_id)).unwrap();
+        store.put_attr(&root_id, "c".to_string(), AttributeData::Place(c_id)).unwrap();
+        assert_eq!(Some(root_id), store.owner_of(&c_id));
+    }
+    
+    #[test]
+    fn relations_by_type() {
+        // Given: three places with relations of two types between them
+        let mut store = HashMapPlaceStore::new();
+        let (a, b, c) = (Place::generate_new(), Place::generate_new(), Place::generate_new());
+        let (a_id, b_id, c_id) = (a.get_id(), b.get_id(), c.get_id());
+        for place in vec![a, b, c] {
+            store.put_place(place).unwrap();
+        }
+        let mut calls = Relation::new("calls".to_string(), a_id, b_id);
+        calls.put_attr("count".to_string(), PrimitiveData::Int(2));
+        let tests = Relation::new("tests".to_string(), c_id, b_id);
+        let also_calls = Relation::new("calls".to_string(), c_id, b_id);
+        for relation in vec![calls.clone(), tests.clone(), also_calls.clone()] {
+            store.put_relation(relation).unwrap();
+        }
+        let ids = |relations: Vec<&Relation>|
+            relations.iter().map(|relation| relation.get_id()).collect::<Vec<_>>();
+        
+        // Then: each type of relation is its own graph
+        assert_eq!(vec![calls.get_id()], ids(store.relations_from(&a_id, "calls")));
+        assert!(store.relations_from(&a_id, "tests").is_empty());
+        let mut callers = vec![calls.get_id(), also_calls.get_id()];
+        callers.sort();
+        assert_eq!(callers, ids(store.relations_to(&b_id, "calls")));
+        assert_eq!(vec![tests.get_id()], ids(store.relations_to(&b_id, "tests")));
+        
+        // When: an attribute of a relation is changed
+        // Then: the relation in the store has it
+        store.put_relation_attr(&calls.get_id(), "count".to_string(), PrimitiveData::Int(3)).unwrap();
+        assert_eq!(Some(&PrimitiveData::Int(3)),
+                   store.get_relation(&calls.get_id()).unwrap().get_attr(&"count".to_string()));
+        
+        // Then: relations must be between places in the store
+        let missing = Place::generate_id();
+        assert_eq!(Err(StoreError::Missing(missing)),
+                   store.put_relation(Relation::new("calls".to_string(), a_id, missing)));
+        
+        // When: a relation is deleted, and then a place
+        // Then: they are gone, along with the relations of the place
+        store.delete_relation(&also_calls.get_id()).unwrap();
+        assert_eq!(Err(StoreError::MissingRelation(also_calls.get_id())),
+                   store.delete_relation(&also_calls.get_id()));
+        store.delete_place(&b_id).unwrap();
+        assert!(store.get_relation(&calls.get_id()).is_none());
+        assert!(store.relations_from(&a_id, "calls").is_empty());
+        assert!(store.relations_from(&c_id, "tests").is_empty());
+    }
+    
+    /// A root that possesses `a`, which possesses `b`, which `c` refers to.
+    fn owned_chain() -> (HashMapPlaceStore, [PlaceId; 4]) {
+        let mut store = HashMapPlaceStore::new();
+        let mut ids = [Place::generate_id(); 4];
+        for id in ids.iter_mut() {
+            let place = Place::generate_new();
+            *id = place.get_id();
+            store.put_place(place).unwrap();
+        }
+        store.set_root(&ids[0]).unwrap();
+        store.put_attr(&ids[1], "b".to_string(), AttributeData::Place(ids[2])).unwrap();
+        store.put_attr(&ids[0], "a".to_string(), AttributeData::Place(ids[1])).unwrap();
+        store.put_attr(&ids[0], "c".to_string(), AttributeData::Place(ids[3])).unwrap();
+        store.put_attr(&ids[3], "dep".to_string(), AttributeData::Reference(ids[2])).unwrap();
+        (store, ids)
+    }
+    
+    #[test]
+    fn delete_policies() {
+        // Given: a possession that is referred to from outside
+        // When: it is deleted with each policy
+        let (mut store, [root, a, b, c]) = owned_chain();
+        
+        // Then: refusing leaves everything as it was
+        assert_eq!(Err(StoreError::Referenced(b)), store.delete_place_with(&a, DeletePolicy::Refuse));
+        assert_eq!(4, store.place_ids().len());
+        assert!(store.check_integrity().broken_references.is_empty());
+        
+        // Then: cascading deletes it and what it possesses, unlinks it from its owner, and leaves
+        // the reference broken
+        assert_eq!(Ok(vec![a, b]), store.delete_place_with(&a, DeletePolicy::Cascade));
+        let remaining: HashSet<PlaceId> = store.place_ids().into_iter().collect();
+        assert_eq!(vec![root, c].into_iter().collect::<HashSet<_>>(), remaining);
+        assert!(store.get_place(&root).unwrap().get_attr(&"a".to_string()).is_none());
+        let report = store.check_integrity();
+        assert!(report.is_consistent());
+        let broken: Vec<PlaceId> = report.broken_references.iter().map(|link| *link.get_curr()).collect();
+        assert_eq!(vec![b], broken);
+        
+        // Then: orphaning deletes only the place, and leaves what it possessed without an owner
+        let (mut store, [_, a, b, _]) = owned_chain();
+        assert_eq!(Ok(vec![a]), store.delete_place_with(&a, DeletePolicy::Orphan));
+        assert_eq!(None, store.owner_of(&b));
+        assert_eq!(vec![b], store.check_integrity().orphans);
+        
+        // Then: places that are only referred to from inside what is deleted can be refused
+        let (mut store, [_, _, _, c]) = owned_chain();
+        store.delete_place_with(&c, DeletePolicy::Refuse).unwrap();
+    }
+    
+    #[test]
+    fn integrity_report() {
+        // Given: a consistent store
+        let (mut store, [_, a, b, _]) = owned_chain();
+        assert_eq!(IntegrityReport::default(), store.check_integrity());
+        
+        // When: a place is deleted without a policy
+        store.delete_place(&a).unwrap();
+        
+        // Then: its owner's possession of it dangles, and what it possessed is an orphan
+        let report = store.check_integrity();
+        assert!(!report.is_consistent());
+        let dangling: Vec<PlaceId> = report.dangling_possessions.iter().map(|link| *link.get_curr()).collect();
+        assert_eq!(vec![a], dangling);
+        assert_eq!(vec![b], report.orphans);
+        assert!(report.ownership_violations.is_empty());
+    }
+    
+    #[test]
+    fn transactions() {
+        // Given: a consistent store
+        let (mut store, [root, a, b, c]) = owned_chain();
+        
+        // When: a possession moves to another owner, which takes it before the first lets go
+        store.begin().unwrap();
+        assert_eq!(Err(StoreError::TransactionOpen), store.begin());
+        store.put_attr(&c, "b".to_string(), AttributeData::Place(b)).unwrap();
+        let mut a_place = store.get_place(&a).unwrap().clone();
+        a_place.remove_attr(&"b".to_string());
+        store.put_place(a_place).unwrap();
+        
+        // Then: it commits, because the store is consistent by then
+        store.commit().unwrap();
+        assert_eq!(Some(c), store.owner_of(&b));
+        assert_eq!(Err(StoreError::NoTransaction), store.commit());
+        
+        // When: a transaction is rolled back
+        // Then: what it deleted and changed is as it was
+        store.begin().unwrap();
+        store.delete_place(&a).unwrap();
+        store.put_attr(&root, "d".to_string(), AttributeData::Data(PrimitiveData::Int(1))).unwrap();
+        store.rollback().unwrap();
+        assert_eq!(Some(root), store.owner_of(&a));
+        assert!(store.get_place(&root).unwrap().get_attr(&"d".to_string()).is_none());
+        assert!(store.check_integrity().is_consistent());
+        
+        // When: transactions would leave a place with two owners or a dangling possession
+        // Then: they fail as a whole
+        let shared = store.transact(|store| {
+            store.put_attr(&root, "e".to_string(), AttributeData::Data(PrimitiveData::Int(1)))?;
+            store.put_attr(&a, "b".to_string(), AttributeData::Place(b))
+        });
+        assert_eq!(Err(StoreError::AlreadyOwned(b)), shared);
+        assert!(store.get_place(&root).unwrap().get_attr(&"e".to_string()).is_none());
+        assert_eq!(Err(StoreError::Missing(a)), store.transact(|store| store.delete_place(&a)));
+        assert_eq!(Some(root), store.owner_of(&a));
+        
+        // Then: so does putting a linked place that would take another's possession
+        let mut taker = Place::generate_new();
+        taker.put_attr("b".to_string(), AttributeData::Place(b));
+        let taker_id = taker.get_id();
+        assert_eq!(Err(StoreError::AlreadyOwned(b)), store.put_linked_place(&a, "taker".to_string(), taker));
+        assert!(store.get_place(&taker_id).is_none());
+        assert!(store.get_place(&a).unwrap().get_attr(&"taker".to_string()).is_none());
+        assert_eq!(IntegrityReport::default(), store.check_integrity());
+        
+        // Then: inside another transaction, a linked place is put as part of it
+        let linked = Place::generate_new();
+        let linked_id = linked.get_id();
+        store.transact(|store| {
+            store.put_linked_place(&a, "linked".to_string(), linked)?;
+            assert_eq!(Some(a), store.owner_of(&linked_id));
+            store.delete_place(&c)
+        }).unwrap_err();
+        assert!(store.get_place(&linked_id).is_none());
+        assert!(store.get_place(&a).unwrap().get_attr(&"linked".to_string()).is_none());
+    }
+}
+
@@ -18,6 +18,21 @@ pub enum PrimitiveData {
     Name(String),
 }
 
+/// Writes the data the way it is written in Shock source, e.g. `10u` or `"text"`.
+impl fmt::Display for PrimitiveData {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), Error> {
+        match self {
+            PrimitiveData::Bool(b) => write!(f, "{}", b),
+            PrimitiveData::Byte(b) => write!(f, "{}u8", b),
+            PrimitiveData::Int(i) => write!(f, "{}", i),
+            PrimitiveData::Unsigned(u) => write!(f, "{}u", u),
+            PrimitiveData::Float(x) => write!(f, "{:?}", x),
+            PrimitiveData::String(s) => write!(f, "{:?}", s),
+            PrimitiveData::Name(name) => write!(f, "{}", name),
+        }
+    }
+}
+
 /// A Place represents a primitive "object" in Shock's primitive system, and can
 /// have attributes. Many Places can form an arbitrary graph.
 ///
@@ -55,13 +70,93 @@ impl fmt::Display for PlaceId {
 }
 //pub type PlaceId = uuid::Uuid;
 
+/// Relations have unique ids too, so that they can be referred to like places.
+#[derive(Copy, Clone, Hash, Ord, PartialOrd, Eq, PartialEq)]
+pub struct RelationId {
+    id: uuid::Uuid,
+}
+
+impl RelationId {
+    fn new() -> RelationId {
+        RelationId { id: uuid::Uuid::new_v4() }
+    }
+}
+
+impl fmt::Debug for RelationId {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), Error> {
+        write!(f, "\"{}\"", self.id.to_simple().to_string())
+    }
+}
+
+impl fmt::Display for RelationId {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), Error> {
+        write!(f, "\"{}\"", self.id.to_simple().to_string())
+    }
+}
+
+/// A Relation is an edge from one Place to another with a type of its own, e.g. `depends_on`,
+/// and attributes of its own. Every type of relation forms a graph over the places.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Relation {
+    id: RelationId,
+    relation_type: String,
+    from: PlaceId,
+    to: PlaceId,
+    attr: HashMap<String, PrimitiveData>,
+}
+
+impl Relation {
+    /// Constructs a new relation with a unique id and no attributes.
+    pub fn new(relation_type: String, from: PlaceId, to: PlaceId) -> Self {
+        Relation { id: RelationId::new(), relation_type, from, to, attr: HashMap::new() }
+    }
+    
+    pub fn get_id(&self) -> RelationId { self.id }
+    pub fn get_type(&self) -> &String { &self.relation_type }
+    pub fn get_from(&self) -> PlaceId { self.from }
+    pub fn get_to(&self) -> PlaceId { self.to }
+    
+    pub fn get_attr(&self, key: &String) -> Option<&PrimitiveData> {
+        self.attr.get(key)
+    }
+    
+    /// Idempotent put into the relation's attribute map by key.
+    pub fn put_attr(&mut self, key: String, value: PrimitiveData) {
+        self.attr.insert(key, value);
+    }
+    
+    /// Deletes an attribute if it is there; no-op if it's not.
+    pub fn remove_attr(&mut self, key: &String) {
+        self.attr.remove(key);
+    }
+    
+    /// The data the relation carries, e.g. a weight or a label, in no particular order.
+    pub fn attributes(&self) -> impl Iterator<Item = (&String, &PrimitiveData)> {
+        self.attr.iter()
+    }
+}
+
 /// Attributes on Places can either be another Place, or primitive data.
 #[derive(Debug, Clone, PartialEq)]
 pub enum AttributeData {
+    /// A place that this place possesses. Every place has only one owner.
     Place(PlaceId),
+    /// A place that this place refers to without owning it. References can be many, and the
+    /// place may have been deleted since.
+    Reference(PlaceId),
     Data(PrimitiveData),
 }
 
+impl AttributeData {
+    /// The place the attribute links to, whether it possesses the place or refers to it.
+    pub fn linked_place(&self) -> Option<PlaceId> {
+        match self {
+            AttributeData::Place(id) | AttributeData::Reference(id) => Some(*id),
+            AttributeData::Data(_) => None,
+        }
+    }
+}
+
 impl Clone for Place {
     fn clone(&self) -> Self {
         Place { id: self.id.clone(), attr: self.attr.clone()}
@@ -121,6 +216,11 @@ impl Place {
         }
     }
     
+    /// All the attributes, in no particular order.
+    pub fn attributes(&self) -> impl Iterator<Item = (&String, &AttributeData)> {
+        self.attr.iter()
+    }
+    
     /// Deletes an attribute if it is there; no-op of it's not.
     pub fn remove_attr(&mut self, key: &String) {
         self.attr.remove(key);
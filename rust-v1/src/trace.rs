@@ -0,0 +1,238 @@
+use crate::interpreter::{Code, Value, ShockError, ErrorKind, Sink};
+use crate::parser::{ExpressionValue, Command};
+
+/// Gets told what a VM is doing, e.g. to trace or profile evaluation. Install one with
+/// `VM::observer` or `Engine::set_observer`. Every method does nothing by default.
+///
+/// Observers are called while the VM is locked, so they must not use the VM themselves.
+pub trait EvalObserver: Send {
+    /// An expression, or a command of a block, is about to be evaluated.
+    fn enter_expression(&mut self, _code: &Code) {}
+
+    /// The expression entered last has a value or failed.
+    fn exit_expression(&mut self, _result: Result<&Value, &ShockError>) {}
+
+    /// A block or procedure call entered a scope of its own.
+    fn push_scope(&mut self) {}
+
+    /// A block or procedure call left its scope.
+    fn pop_scope(&mut self) {}
+
+    /// A native was called. `name` is the name it was called by.
+    fn native_call(&mut self, _name: &str, _args: &[(String, Value)], _result: Result<&Value, &ShockError>) {}
+}
+
+/// Writes every expression and what it evaluated to, indented by how deeply it is nested.
+pub struct Tracer {
+    sink: Sink,
+    depth: usize,
+}
+
+impl Tracer {
+    pub fn new(sink: Sink) -> Tracer {
+        Tracer { sink, depth: 0 }
+    }
+
+    fn line(&mut self, text: &str) {
+        let indent = "  ".repeat(self.depth);
+        // Tracing is best-effort, so a sink that fails does not fail the evaluation.
+        let _ = writeln!(self.sink.lock().unwrap(), "{}{}", indent, text);
+    }
+}
+
+impl EvalObserver for Tracer {
+    fn enter_expression(&mut self, code: &Code) {
+        self.line(&format!("-> {}", code.summary()));
+        self.depth += 1;
+    }
+
+    fn exit_expression(&mut self, result: Result<&Value, &ShockError>) {
+        // Continuations can leave expressions without exiting them, so the depth may be off.
+        self.depth = self.depth.saturating_sub(1);
+        match result {
+            Ok(value) => self.line(&format!("<- {:?}", value)),
+            Err(err) => self.line(&format!("!! error[{}]: {}", err.kind.name(), err.message)),
+        }
+    }
+
+    fn native_call(&mut self, name: &str, _args: &[(String, Value)], result: Result<&Value, &ShockError>) {
+        if let Err(err) = result {
+            self.line(&format!("`{}` failed: {}", name, err.message));
+        }
+    }
+}
+
+/// Something a `Recorder` saw.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Event {
+    /// An expression was entered. It is given as a summary of its source.
+    Enter(String),
+    Exit(Result<Value, ErrorKind>),
+    PushScope,
+    PopScope,
+    Native { name: String, args: Vec<Value>, result: Result<Value, ErrorKind> },
+}
+
+/// Keeps everything it sees, e.g. for tests that check how code was evaluated.
+#[derive(Debug, Default)]
+pub struct Recorder {
+    pub events: Vec<Event>,
+}
+
+impl EvalObserver for Recorder {
+    fn enter_expression(&mut self, code: &Code) {
+        self.events.push(Event::Enter(code.summary()));
+    }
+
+    fn exit_expression(&mut self, result: Result<&Value, &ShockError>) {
+        self.events.push(Event::Exit(result.cloned().map_err(|err| err.kind)));
+    }
+
+    fn push_scope(&mut self) {
+        self.events.push(Event::PushScope);
+    }
+
+    fn pop_scope(&mut self) {
+        self.events.push(Event::PopScope);
+    }
+
+    fn native_call(&mut self, name: &str, args: &[(String, Value)], result: Result<&Value, &ShockError>) {
+        self.events.push(Event::Native {
+            name: name.to_owned(),
+            args: args.iter().map(|(_, arg)| arg.clone()).collect(),
+            result: result.cloned().map_err(|err| err.kind),
+        });
+    }
+}
+
+/// Writes an expression back as a line of source, with the insides of blocks and procedures left
+/// out.
+pub fn summarize(expr: &ExpressionValue) -> String {
+    let join = |exprs: &mut dyn Iterator<Item = String>| exprs.collect::<Vec<String>>().join(", ");
+    match expr {
+        ExpressionValue::Primitive(primitive, _) => primitive.to_string(),
+        ExpressionValue::Expression(command) => format!("({})", summarize_command(command)),
+        ExpressionValue::Block(..) => "{ ... }".to_owned(),
+        ExpressionValue::Procedure(..) => "[...]{ ... }".to_owned(),
+        ExpressionValue::Path(components, _) => components.iter().map(|part| format!(".{}", part)).collect(),
+        ExpressionValue::List(elements, _) => format!("[{}]", join(&mut elements.iter().map(summarize))),
+        ExpressionValue::Struct(fields, _) =>
+            format!("({})", join(&mut fields.iter().map(|(name, field)| format!("{}: {}", name, summarize(field))))),
+        ExpressionValue::Unit => "()".to_owned(),
+    }
+}
+
+/// Writes a command back as a line of source, like `summarize`.
+pub fn summarize_command(command: &Command) -> String {
+    let mut summary = command.name.clone();
+    for (label, arg) in command.args.iter() {
+        summary += " ";
+        if !label.is_empty() {
+            summary += &format!("{}: ", label);
+        }
+        summary += &summarize(arg);
+    }
+    summary
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Engine;
+    use crate::interpreter::{Value, ErrorKind};
+    use crate::model::PrimitiveData;
+    use crate::trace::{Recorder, Tracer, Event};
+    use crate::engine::tests::Buffer;
+    use std::sync::{Arc, Mutex};
+
+    fn int(i: i64) -> Value {
+        Value::Primitive(PrimitiveData::Int(i))
+    }
+
+    fn recording() -> (Engine, Arc<Mutex<Recorder>>) {
+        let mut engine = Engine::new();
+        let recorder = Arc::new(Mutex::new(Recorder::default()));
+        engine.set_observer(Some(recorder.clone()));
+        (engine, recorder)
+    }
+
+    #[test]
+    fn records_expressions_and_native_calls() {
+        // Given: an engine with a recorder
+        let (mut engine, recorder) = recording();
+
+        // When: it evaluates a command
+        engine.eval_str("+ 1 2").unwrap();
+
+        // Then: the arguments are entered and exited before the native is called
+        assert_eq!(vec![
+            Event::Enter("(+ 1 2)".to_owned()),
+            Event::Enter("1".to_owned()),
+            Event::Exit(Ok(int(1))),
+            Event::Enter("2".to_owned()),
+            Event::Exit(Ok(int(2))),
+            Event::Native { name: "+".to_owned(), args: vec![int(1), int(2)], result: Ok(int(3)) },
+            Event::Exit(Ok(int(3))),
+        ], recorder.lock().unwrap().events);
+    }
+
+    #[test]
+    fn records_scopes_and_errors() {
+        // Given: a procedure with a block in it
+        let (mut engine, recorder) = recording();
+        engine.eval_str("let f = [x: Int]{ let y = { + x 1 } }").unwrap();
+        recorder.lock().unwrap().events.clear();
+
+        // When: it is called
+        // Then: its scope and the block's scope are pushed and popped in order
+        engine.eval_str("f 1").unwrap();
+        let scopes: Vec<Event> = recorder.lock().unwrap().events.iter()
+            .filter(|event| **event == Event::PushScope || **event == Event::PopScope)
+            .cloned()
+            .collect();
+        assert_eq!(vec![Event::PushScope, Event::PushScope, Event::PopScope, Event::PopScope], scopes);
+
+        // When: a call fails
+        // Then: every expression it unwinds through exits with the error
+        recorder.lock().unwrap().events.clear();
+        engine.eval_str("f \"a\"").unwrap_err();
+        let events = recorder.lock().unwrap().events.clone();
+        assert_eq!(Some(&Event::Exit(Err(ErrorKind::TypeMismatch))), events.last());
+        let enters = events.iter().filter(|event| matches!(event, Event::Enter(_))).count();
+        let exits = events.iter().filter(|event| matches!(event, Event::Exit(_))).count();
+        assert_eq!(enters, exits);
+        assert_eq!(events.iter().filter(|event| **event == Event::PushScope).count(),
+                   events.iter().filter(|event| **event == Event::PopScope).count());
+    }
+
+    #[test]
+    fn tracer_indents_nested_expressions() {
+        // Given: an engine with a tracer
+        let mut engine = Engine::new();
+        let buffer = Buffer::default();
+        engine.set_observer(Some(Arc::new(Mutex::new(Tracer::new(Arc::new(Mutex::new(buffer.clone())))))));
+
+        // When: it evaluates a nested command
+        engine.eval_str("+ 1 (* 2 3)").unwrap();
+
+        // Then: each expression and its value are written at its depth
+        let trace = buffer.contents();
+        assert_eq!(vec![
+            "-> (+ 1 (* 2 3))",
+            "  -> 1",
+            "  <- Primitive(Int(1))",
+            "  -> (* 2 3)",
+            "    -> 2",
+            "    <- Primitive(Int(2))",
+            "    -> 3",
+            "    <- Primitive(Int(3))",
+            "  <- Primitive(Int(6))",
+            "<- Primitive(Int(7))",
+        ], trace.lines().collect::<Vec<&str>>());
+
+        // When: the tracer is removed
+        // Then: nothing more is written
+        engine.set_observer(None);
+        engine.eval_str("+ 1 1").unwrap();
+        assert_eq!(trace, buffer.contents());
+    }
+}